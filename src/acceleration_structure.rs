@@ -1,174 +1,64 @@
+use serde::Deserialize;
 use slotmap::DefaultKey;
 use slotmap::SlotMap;
 
 use super::bounding_box::*;
 use super::hittable::*;
-use super::rand::*;
 use super::ray::*;
 use super::scene::*;
-use super::types::{Position, Transform};
+use super::types::Position;
 use super::vec::*;
 
-trait Node {
-    fn hit_test(&self, ray: &Ray, t_min: f32, t_max: f32, result: Vec<u32>) -> Vec<u32>;
-}
-
-struct Branch {
-    left: Box<dyn Node>,
-    right: Box<dyn Node>,
-    bounding_box: BoundingBox,
-}
-
-impl Node for Branch {
-    fn hit_test(&self, ray: &Ray, t_min: f32, t_max: f32, result: Vec<u32>) -> Vec<u32> {
-        if !self.bounding_box.hit(ray, t_min, t_max) {
-            result
-        } else {
-            let ids = self.left.hit_test(ray, t_min, t_max, result);
-            let r = self.right.hit_test(ray, t_min, t_max, ids);
-            r
-        }
-    }
-}
-
-struct Leaf {
-    id: u32,
-    bounding_box: BoundingBox,
-}
-
-impl Leaf {
-    fn new(instance: (u32, BoundingBox)) -> Box<dyn Node> {
-        Box::new(Self {
-            id: instance.0,
-            bounding_box: instance.1,
-        })
-    }
-}
-
-impl Node for Leaf {
-    fn hit_test(&self, ray: &Ray, t_min: f32, t_max: f32, mut result: Vec<u32>) -> Vec<u32> {
-        if self.bounding_box.hit(ray, t_min, t_max) {
-            result.push(self.id);
-            result
-        } else {
-            result
-        }
-    }
-}
-
-impl Branch {
-    fn new(instances: &mut Vec<(u32, BoundingBox)>) -> Box<dyn Node> {
-        let mut bounding_box = BoundingBox::new(
-            Position::from_values([0., 0., 0.]),
-            Position::from_values([0., 0., 0.]),
-        );
-        for (_, bb) in instances.iter() {
-            bounding_box = BoundingBox::surrounding_box(&bounding_box, &bb);
-        }
-
-        let i = int_range(0, 3) as usize;
-        instances.sort_by(|(_, bb), (_, bb2)| bb.min()[i].partial_cmp(&bb2.max()[i]).unwrap());
-
-        let mid = instances.len() / 2;
-        let (left_instances, right_instances) = instances.split_at_mut(mid);
-        let left = Self::from_slice(left_instances);
-        let right = Self::from_slice(right_instances);
-        Box::new(Self {
-            left,
-            right,
-            bounding_box,
-        })
-    }
-
-    fn from_slice(slice: &mut [(u32, BoundingBox)]) -> Box<dyn Node> {
-        let mut bounding_box = BoundingBox::new(
-            Position::from_values([0., 0., 0.]),
-            Position::from_values([0., 0., 0.]),
-        );
-        for (_, bb) in slice.iter() {
-            bounding_box = BoundingBox::surrounding_box(&bounding_box, &bb);
-        }
-
-        if slice.len() == 1 {
-            return Box::new(Leaf {
-                id: slice[0].0,
-                bounding_box: slice[0].1,
-            });
-        }
-
-        let mid = slice.len() / 2;
-        let (left_instances, right_instances) = slice.split_at_mut(mid);
-        let left = Self::from_slice(left_instances);
-        let right = Self::from_slice(right_instances);
-        Box::new(Self {
-            left,
-            right,
-            bounding_box,
-        })
-    }
-}
 pub struct TopLevelAccelerationStructure {
     instances: Vec<Instance>,
-    bounding_box: BoundingBox,
-    root_node: Box<dyn Node>,
+    bvh: Bvh,
 }
 
-unsafe impl Send for TopLevelAccelerationStructure {}
-unsafe impl Sync for TopLevelAccelerationStructure {}
-
 impl TopLevelAccelerationStructure {
-    pub fn new(
-        hittables: &SlotMap<DefaultKey, Box<dyn Hittable>>,
-        instances: &Vec<Instance>,
-    ) -> Self {
-        let geometry = hittables.clone();
-        let geometry_instances = instances.clone();
-        let mut id_and_bb = Vec::new();
-
-        // collect bounding boxes and id's
-        for instance in instances.iter() {
-            id_and_bb.push((
-                instance.instance_id,
-                geometry[instance.geometry_index]
-                    .bounding_box()
-                    .unwrap()
-                    .transformed(&instance.transform),
-            ))
-        }
-
-        // sort them on x-axis (arbitrary axis)
-        id_and_bb.sort_by(|(_, bb), (_, bb2)| bb.min().x().partial_cmp(&bb2.max().x()).unwrap());
-        let mut bounding_box = BoundingBox::new(
-            Position::from_values([0., 0., 0.]),
-            Position::from_values([0., 0., 0.]),
-        );
+    // Instances of geometry without bounds, like empty meshes, can't be hit
+    // and are left out of the BVH.
+    pub fn new(hittables: &SlotMap<DefaultKey, Box<dyn Hittable>>, instances: &[Instance]) -> Self {
+        let (ids, bounding_boxes): (Vec<u32>, Vec<BoundingBox>) = instances
+            .iter()
+            .enumerate()
+            .filter_map(|(id, instance)| {
+                let bounding_box = hittables[instance.geometry_index].bounding_box()?;
+                Some((id as u32, bounding_box.transformed(&instance.transform)))
+            })
+            .unzip();
 
-        // calculate the bounding box of the entire structure.
-        for (_, bb) in id_and_bb.iter() {
-            bounding_box = BoundingBox::surrounding_box(&bounding_box, &bb);
+        let mut bvh = Bvh::build_sah(&bounding_boxes);
+        for index in bvh.primitive_indices.iter_mut() {
+            *index = ids[*index as usize];
         }
-
-        let root_node = if id_and_bb.len() > 1 {
-            Branch::new(&mut id_and_bb)
-        } else {
-            Leaf::new(id_and_bb[0])
-        };
-
         Self {
-            //hittables: geometry,
-            instances: geometry_instances,
-            bounding_box,
-            root_node,
+            instances: instances.to_vec(),
+            bvh,
         }
     }
 
-    pub fn intersect_instance(&self, ray: &Ray, t_min: f32, t_max: f32) -> Vec<u32> {
-        let results = Vec::new();
-        if self.bounding_box.hit(ray, t_min, t_max) {
-            self.root_node.hit_test(ray, t_min, t_max, results)
-        } else {
-            results
-        }
+    // Finds the closest instance hit along a world space ray, `intersect` is
+    // called like in `BottomLevelAccelerationStructure::closest_hit` with
+    // instance ids.
+    pub fn closest_hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        intersect: impl FnMut(u32, f32) -> Option<f32>,
+    ) -> Option<f32> {
+        self.bvh.closest_hit(ray, t_min, t_max, intersect)
+    }
+
+    // Like `closest_hit`, but returns as soon as any instance is hit.
+    pub fn any_hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        intersect: impl FnMut(u32, f32) -> Option<f32>,
+    ) -> bool {
+        self.bvh.any_hit(ray, t_min, t_max, intersect)
     }
 
     pub fn geometry(&self, index: usize) {
@@ -180,15 +70,44 @@ impl TopLevelAccelerationStructure {
     }
 }
 
+// Selects how a bottom level acceleration structure is built.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildQuality {
+    // Linear BVH from Morton codes, quick to build.
+    Fast,
+    // Binned surface area heuristic, slower to build but faster to trace.
+    #[default]
+    High,
+}
+
+// Nodes are stored depth first. The first child of a branch directly follows
+// it and `offset` is the index of the second child. Leaves have a non zero
+// `primitive_count` and `offset` points into the primitive indices.
+#[derive(Clone, Copy, Default)]
+struct BvhNode {
+    bounding_box: BoundingBox,
+    offset: u32,
+    primitive_count: u32,
+    // The split axis, which tells the traversal which child is nearer.
+    axis: u32,
+}
+
+impl BvhNode {
+    fn is_leaf(&self) -> bool {
+        self.primitive_count > 0
+    }
+}
+
 #[derive(Default, Clone)]
-struct BVHFlatNode {
+struct LinearBvhNode {
     parent_idx: u32,
     left_child_idx: u32,
     right_child_idx: u32,
     primitive_idx: u32,
 }
 
-impl BVHFlatNode {
+impl LinearBvhNode {
     fn is_leaf(&self) -> bool {
         self.primitive_idx != 0xFFFFFFFF
     }
@@ -226,81 +145,395 @@ impl MortonCode {
     }
 }
 
+// The primitive bounds the SAH builder partitions.
+#[derive(Clone, Copy)]
+struct BuildPrimitive {
+    bounding_box: BoundingBox,
+    center: Position,
+    index: u32,
+}
+
+// The cheapest binned split, primitives in bins below `bin` go left.
+struct SahSplit {
+    cost: f32,
+    axis: usize,
+    bin: usize,
+    centers_min: f32,
+    extent: f32,
+}
+
+impl SahSplit {
+    // Uses the same binning as the cost estimate, comparing against a split
+    // position instead can round differently for centers far from the origin.
+    fn goes_left(&self, primitive: &BuildPrimitive) -> bool {
+        sah_bin(primitive.center[self.axis], self.centers_min, self.extent) < self.bin
+    }
+}
+
+const BIN_COUNT: usize = 16;
+// Traversal keeps its stack on the stack for trees up to this depth, deeper
+// trees fall back to a heap allocated one.
+const STACK_SIZE: usize = 64;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f32 = 1.;
+const INTERSECTION_COST: f32 = 1.;
+
 pub struct BottomLevelAccelerationStructure {
-    total_bb: BoundingBox,
-    bbs: Vec<BoundingBox>,
-    nodes: Vec<BVHFlatNode>,
+    bvh: Bvh,
 }
 
 impl BottomLevelAccelerationStructure {
-    pub fn bounding_box(&self) -> BoundingBox {
-        self.total_bb
+    pub fn new(vertices: &[Position], indices: &[u32], quality: BuildQuality) -> Self {
+        let bounding_boxes: Vec<BoundingBox> = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let v0 = &vertices[triangle[0] as usize];
+                let v1 = &vertices[triangle[1] as usize];
+                let v2 = &vertices[triangle[2] as usize];
+                BoundingBox::new(min(v0, &min(v1, v2)), max(v0, &max(v1, v2)))
+            })
+            .collect();
+
+        let bvh = match quality {
+            BuildQuality::Fast => Bvh::build_linear(&bounding_boxes),
+            BuildQuality::High => Bvh::build_sah(&bounding_boxes),
+        };
+        Self { bvh }
+    }
+
+    // None when there are no triangles.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.bvh.nodes.first().map(|node| node.bounding_box)
     }
 
-    fn hit_internal(
+    // Finds the closest primitive along a ray given in object space. The
+    // nodes are visited front to back and `intersect` is called with the
+    // primitive index and the current `t_max` for every primitive in a leaf
+    // the ray reaches. It returns the hit distance if the primitive is hit
+    // closer than that, which then shrinks the search interval.
+    pub fn closest_hit(
         &self,
-        object_to_world: &Transform,
         ray: &Ray,
         t_min: f32,
         t_max: f32,
-        node: &BVHFlatNode,
-        mut results: Vec<u32>,
-    ) -> Vec<u32> {
-        let left_bb = &self.bbs[node.left_child_idx as usize];
-        let right_bb = &self.bbs[node.right_child_idx as usize];
-        let left_hit = left_bb.transformed(object_to_world).hit(ray, t_min, t_max);
-        if left_hit {
-            if self.nodes[node.left_child_idx as usize].is_leaf() {
-                results.push(self.nodes[node.left_child_idx as usize].primitive_idx)
-            } else {
-                results = self.hit_internal(
-                    object_to_world,
-                    ray,
-                    t_min,
-                    t_max,
-                    &self.nodes[node.left_child_idx as usize],
-                    results,
-                )
-            }
+        intersect: impl FnMut(u32, f32) -> Option<f32>,
+    ) -> Option<f32> {
+        self.bvh.closest_hit(ray, t_min, t_max, intersect)
+    }
+
+    // Like `closest_hit`, but returns as soon as any primitive is hit.
+    pub fn any_hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        intersect: impl FnMut(u32, f32) -> Option<f32>,
+    ) -> bool {
+        self.bvh.any_hit(ray, t_min, t_max, intersect)
+    }
+}
+
+// The node array and primitive order shared by both acceleration structure
+// levels. A BVH over no primitives has no nodes.
+#[derive(Default)]
+struct Bvh {
+    nodes: Vec<BvhNode>,
+    primitive_indices: Vec<u32>,
+    // Edges on the longest path from the root to a leaf, the traversal stack
+    // never holds more than one entry above that.
+    depth: usize,
+}
+
+impl Bvh {
+    // With `any_hit` set the traversal stops at the first primitive that is
+    // hit, otherwise it keeps going to find the closest one.
+    fn traverse(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        mut t_max: f32,
+        any_hit: bool,
+        mut intersect: impl FnMut(u32, f32) -> Option<f32>,
+    ) -> Option<f32> {
+        if self.nodes.is_empty() {
+            return None;
         }
-        let right_hit = right_bb.transformed(object_to_world).hit(ray, t_min, t_max);
-        if right_hit {
-            if self.nodes[node.right_child_idx as usize].is_leaf() {
-                results.push(self.nodes[node.right_child_idx as usize].primitive_idx)
+
+        let mut closest = None;
+        let mut fixed = [0u32; STACK_SIZE];
+        let mut heap = Vec::new();
+        let stack: &mut [u32] = if self.depth < STACK_SIZE {
+            &mut fixed
+        } else {
+            heap.resize(self.depth + 1, 0);
+            &mut heap
+        };
+        let mut stack_size = 1;
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size] as usize];
+            if !node.bounding_box.hit(ray, t_min, t_max) {
+                continue;
+            }
+
+            if node.is_leaf() {
+                let first = node.offset as usize;
+                let last = first + node.primitive_count as usize;
+                for primitive in self.primitive_indices[first..last].iter() {
+                    if let Some(t) = intersect(*primitive, t_max) {
+                        if any_hit {
+                            return Some(t);
+                        }
+
+                        t_max = t;
+                        closest = Some(t);
+                    }
+                }
             } else {
-                results = self.hit_internal(
-                    object_to_world,
-                    ray,
-                    t_min,
-                    t_max,
-                    &self.nodes[node.right_child_idx as usize],
-                    results,
-                )
+                // Push the far child first so the near one is visited next.
+                let first = stack[stack_size] + 1;
+                let (near, far) = if ray.direction()[node.axis as usize] < 0. {
+                    (node.offset, first)
+                } else {
+                    (first, node.offset)
+                };
+                debug_assert!(stack_size + 2 <= stack.len());
+                stack[stack_size] = far;
+                stack[stack_size + 1] = near;
+                stack_size += 2;
             }
         }
-        results
+
+        closest
     }
 
-    pub fn hit_test(
+    fn closest_hit(
         &self,
-        object_to_world: &Transform,
         ray: &Ray,
         t_min: f32,
         t_max: f32,
-    ) -> Vec<u32> {
-        let mut results = Vec::new();
-        if self.bbs[0]
-            .transformed(object_to_world)
-            .hit(ray, t_min, t_max)
-        {
-            results =
-                self.hit_internal(object_to_world, ray, t_min, t_max, &self.nodes[0], results);
+        intersect: impl FnMut(u32, f32) -> Option<f32>,
+    ) -> Option<f32> {
+        self.traverse(ray, t_min, t_max, false, intersect)
+    }
+
+    fn any_hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        intersect: impl FnMut(u32, f32) -> Option<f32>,
+    ) -> bool {
+        self.traverse(ray, t_min, t_max, true, intersect).is_some()
+    }
+
+    fn build_sah(bounding_boxes: &[BoundingBox]) -> Self {
+        if bounding_boxes.is_empty() {
+            return Self::default();
+        }
+
+        let mut primitives: Vec<BuildPrimitive> = bounding_boxes
+            .iter()
+            .enumerate()
+            .map(|(index, bounding_box)| BuildPrimitive {
+                bounding_box: *bounding_box,
+                center: bounding_box.center(),
+                index: index as u32,
+            })
+            .collect();
+
+        let mut nodes = Vec::with_capacity(2 * primitives.len());
+        Self::build_sah_node(&mut primitives, 0, &mut nodes);
+
+        Self {
+            depth: max_depth(&nodes),
+            nodes,
+            primitive_indices: primitives.iter().map(|p| p.index).collect(),
+        }
+    }
+
+    // Builds the subtree over `primitives`, which start at `offset` in the
+    // final primitive order, and returns the index of its root.
+    fn build_sah_node(
+        primitives: &mut [BuildPrimitive],
+        offset: usize,
+        nodes: &mut Vec<BvhNode>,
+    ) -> usize {
+        let index = nodes.len();
+        let bounding_box = primitives
+            .iter()
+            .map(|p| p.bounding_box)
+            .reduce(|a, b| BoundingBox::surrounding_box(&a, &b))
+            .unwrap();
+        let leaf = BvhNode {
+            bounding_box,
+            offset: offset as u32,
+            primitive_count: primitives.len() as u32,
+            axis: 0,
+        };
+        nodes.push(leaf);
+        if primitives.len() == 1 {
+            return index;
+        }
+
+        let split = Self::find_sah_split(primitives, &bounding_box);
+        let leaf_cost = INTERSECTION_COST * primitives.len() as f32;
+        let (axis, mid) = match split {
+            Some(split) if split.cost >= leaf_cost && primitives.len() <= MAX_LEAF_SIZE => {
+                return index
+            }
+            Some(split) => {
+                let mut mid = 0;
+                for i in 0..primitives.len() {
+                    if split.goes_left(&primitives[i]) {
+                        primitives.swap(i, mid);
+                        mid += 1;
+                    }
+                }
+                // Never recurse into an empty side, split at the median instead.
+                if mid == 0 || mid == primitives.len() {
+                    mid = primitives.len() / 2;
+                    primitives.select_nth_unstable_by(mid, |a, b| {
+                        a.center[split.axis].total_cmp(&b.center[split.axis])
+                    });
+                }
+                (split.axis, mid)
+            }
+            // All centers coincide, so there is nothing to gain from a split.
+            None if primitives.len() <= MAX_LEAF_SIZE => return index,
+            None => (0, primitives.len() / 2),
+        };
+
+        let (left, right) = primitives.split_at_mut(mid);
+        Self::build_sah_node(left, offset, nodes);
+        let second = Self::build_sah_node(right, offset + mid, nodes);
+        nodes[index] = BvhNode {
+            bounding_box,
+            offset: second as u32,
+            primitive_count: 0,
+            axis: axis as u32,
+        };
+
+        index
+    }
+
+    // Bins the primitive centers along every axis and returns the cheapest
+    // split between bins.
+    fn find_sah_split(
+        primitives: &[BuildPrimitive],
+        bounding_box: &BoundingBox,
+    ) -> Option<SahSplit> {
+        let centers_min = primitives
+            .iter()
+            .fold(Position::splat(f32::MAX), |m, p| min(&m, &p.center));
+        let centers_max = primitives
+            .iter()
+            .fold(Position::splat(f32::MIN), |m, p| max(&m, &p.center));
+        let area = bounding_box.surface_area();
+
+        let mut best: Option<SahSplit> = None;
+        for axis in 0..3 {
+            let extent = centers_max[axis] - centers_min[axis];
+            if extent <= 0. {
+                continue;
+            }
+
+            let mut bins: [(Option<BoundingBox>, usize); BIN_COUNT] = [(None, 0); BIN_COUNT];
+            for p in primitives.iter() {
+                let bin = &mut bins[sah_bin(p.center[axis], centers_min[axis], extent)];
+                bin.0 = Some(match bin.0 {
+                    Some(bb) => BoundingBox::surrounding_box(&bb, &p.bounding_box),
+                    None => p.bounding_box,
+                });
+                bin.1 += 1;
+            }
+
+            // Sweep from the right to get the cost of every right side first.
+            let mut right_costs = [0.; BIN_COUNT];
+            let mut right_box: Option<BoundingBox> = None;
+            let mut right_count = 0;
+            for split in (1..BIN_COUNT).rev() {
+                right_box = merge(right_box, bins[split].0);
+                right_count += bins[split].1;
+                right_costs[split] =
+                    right_box.map_or(0., |bb| bb.surface_area()) * right_count as f32;
+            }
+
+            let mut left_box: Option<BoundingBox> = None;
+            let mut left_count = 0;
+            for split in 1..BIN_COUNT {
+                left_box = merge(left_box, bins[split - 1].0);
+                left_count += bins[split - 1].1;
+                if left_count == 0 || left_count == primitives.len() {
+                    continue;
+                }
+
+                let left_cost = left_box.map_or(0., |bb| bb.surface_area()) * left_count as f32;
+                let cost =
+                    TRAVERSAL_COST + INTERSECTION_COST * (left_cost + right_costs[split]) / area;
+                if best.as_ref().is_none_or(|best| cost < best.cost) {
+                    best = Some(SahSplit {
+                        cost,
+                        axis,
+                        bin: split,
+                        centers_min: centers_min[axis],
+                        extent,
+                    });
+                }
+            }
         }
 
-        results
+        best
+    }
+
+    fn build_linear(bounding_boxes: &[BoundingBox]) -> Self {
+        if bounding_boxes.is_empty() {
+            return Self::default();
+        }
+
+        let (linear_nodes, bbs) = Self::build_linear_nodes(bounding_boxes);
+        let mut result = Self {
+            nodes: Vec::with_capacity(linear_nodes.len()),
+            primitive_indices: Vec::with_capacity(bounding_boxes.len()),
+            depth: 0,
+        };
+        result.flatten_linear(&linear_nodes, &bbs, 0);
+        result.depth = max_depth(&result.nodes);
+        result
+    }
+
+    // Converts the Karras tree to the depth first node layout.
+    fn flatten_linear(&mut self, linear_nodes: &[LinearBvhNode], bbs: &[BoundingBox], i: usize) {
+        let node = &linear_nodes[i];
+        let bounding_box = bbs[i];
+        let index = self.nodes.len();
+        if node.is_leaf() {
+            self.nodes.push(BvhNode {
+                bounding_box,
+                offset: self.primitive_indices.len() as u32,
+                primitive_count: 1,
+                axis: 0,
+            });
+            self.primitive_indices.push(node.primitive_idx);
+            return;
+        }
+
+        let dimensions = bounding_box.dimensions();
+        let axis = (0..3)
+            .max_by(|a, b| dimensions[*a].partial_cmp(&dimensions[*b]).unwrap())
+            .unwrap();
+        self.nodes.push(BvhNode {
+            bounding_box,
+            offset: 0,
+            primitive_count: 0,
+            axis: axis as u32,
+        });
+        self.flatten_linear(linear_nodes, bbs, node.left_child_idx as usize);
+        self.nodes[index].offset = self.nodes.len() as u32;
+        self.flatten_linear(linear_nodes, bbs, node.right_child_idx as usize);
     }
 
-    fn find_range(codes: &Vec<MortonCode>, mut idx: usize) -> (usize, usize) {
+    fn find_range(codes: &[MortonCode], mut idx: usize) -> (usize, usize) {
         if idx == 0 {
             return (0, codes.len() - 1);
         }
@@ -349,7 +582,7 @@ impl BottomLevelAccelerationStructure {
         (idx, jdx as usize)
     }
 
-    fn find_split(codes: &Vec<MortonCode>, first: usize, last: usize) -> usize {
+    fn find_split(codes: &[MortonCode], first: usize, last: usize) -> usize {
         let c1 = codes[first].code;
         let c2 = codes[last].code;
         if c1 == c2 {
@@ -374,8 +607,8 @@ impl BottomLevelAccelerationStructure {
         split
     }
 
-    pub fn new(vertices: &Vec<Position>, indices: Option<&Vec<u32>>) -> Self {
-        let leaf_count = indices.unwrap().len() / 3;
+    fn build_linear_nodes(triangle_bbs: &[BoundingBox]) -> (Vec<LinearBvhNode>, Vec<BoundingBox>) {
+        let leaf_count = triangle_bbs.len();
         let branch_count = leaf_count - 1;
         let total_node_count = leaf_count + branch_count;
         let mut primitive_bbs = vec![BoundingBox::default(); total_node_count];
@@ -383,26 +616,9 @@ impl BottomLevelAccelerationStructure {
             Position::from_values([f32::MAX, f32::MAX, f32::MAX]),
             Position::from_values([f32::MIN, f32::MIN, f32::MIN]),
         );
-        if let Some(indices) = &indices {
-            for i in (0..indices.len()).step_by(3) {
-                let idx0 = indices[i] as usize;
-                let idx1 = indices[i + 1] as usize;
-                let idx2 = indices[i + 2] as usize;
-                let min = min(&vertices[idx0], &min(&vertices[idx1], &vertices[idx2]));
-                let max = max(&vertices[idx0], &max(&vertices[idx1], &vertices[idx2]));
-                let triangle_bb = BoundingBox::new(min, max);
-                total_bb = BoundingBox::surrounding_box(&triangle_bb, &total_bb);
-                let leaf_idx = (i / 3) + branch_count;
-                primitive_bbs[leaf_idx] = triangle_bb;
-            }
-        } else {
-            for i in (0..vertices.len()).step_by(3) {
-                let min = min(&vertices[i], &min(&vertices[i + 1], &vertices[i + 2]));
-                let max = max(&vertices[i], &max(&vertices[i + 1], &vertices[i + 2]));
-                let triangle_bb = BoundingBox::new(min, max);
-                total_bb = BoundingBox::surrounding_box(&triangle_bb, &total_bb);
-                primitive_bbs.push(triangle_bb);
-            }
+        for (i, triangle_bb) in triangle_bbs.iter().enumerate() {
+            total_bb = BoundingBox::surrounding_box(triangle_bb, &total_bb);
+            primitive_bbs[i + branch_count] = *triangle_bb;
         }
 
         let mut morton_codes: Vec<MortonCode> = primitive_bbs
@@ -426,14 +642,14 @@ impl BottomLevelAccelerationStructure {
         }
 
         primitive_bbs = b;
-        let mut nodes = vec![BVHFlatNode::default(); total_node_count];
+        let mut nodes = vec![LinearBvhNode::default(); total_node_count];
         nodes[0].parent_idx = 0xFFFFFFFF;
-        for i in 0..branch_count {
-            nodes[i].primitive_idx = 0xFFFFFFFF;
+        for node in nodes.iter_mut().take(branch_count) {
+            node.primitive_idx = 0xFFFFFFFF;
         }
 
-        for i in branch_count..total_node_count {
-            nodes[i].primitive_idx = morton_codes[i - branch_count].primitive_id;
+        for (node, code) in nodes.iter_mut().skip(branch_count).zip(&morton_codes) {
+            node.primitive_idx = code.primitive_id;
         }
 
         for i in 0..branch_count {
@@ -477,10 +693,197 @@ impl BottomLevelAccelerationStructure {
             }
         }
 
-        Self {
-            total_bb,
-            bbs: primitive_bbs,
-            nodes,
+        (nodes, primitive_bbs)
+    }
+}
+
+// Children always follow their parent in the depth first layout, so a single
+// forward pass sees every parent before its children.
+fn max_depth(nodes: &[BvhNode]) -> usize {
+    let mut depths = vec![0; nodes.len()];
+    for (index, node) in nodes.iter().enumerate() {
+        if !node.is_leaf() {
+            depths[index + 1] = depths[index] + 1;
+            depths[node.offset as usize] = depths[index] + 1;
+        }
+    }
+    depths.into_iter().max().unwrap_or(0)
+}
+
+fn sah_bin(center: f32, centers_min: f32, extent: f32) -> usize {
+    let relative = (center - centers_min) / extent;
+    ((relative * BIN_COUNT as f32) as usize).min(BIN_COUNT - 1)
+}
+
+fn merge(a: Option<BoundingBox>, b: Option<BoundingBox>) -> Option<BoundingBox> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(BoundingBox::surrounding_box(&a, &b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+#[cfg(test)]
+mod acceleration_structure_tests {
+    use slotmap::DefaultKey;
+
+    use super::{
+        max_depth, BottomLevelAccelerationStructure, BuildQuality, Bvh, BvhNode,
+        TopLevelAccelerationStructure, STACK_SIZE,
+    };
+    use crate::bounding_box::BoundingBox;
+    use crate::hittable::{Sphere, TriangleMesh};
+    use crate::ray::Ray;
+    use crate::resources::Resources;
+    use crate::scene::Instance;
+    use crate::types::*;
+
+    #[test]
+    fn test_build_qualities() {
+        // A row of 64 unit quads along x, two triangles each.
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for i in 0..64 {
+            let base = vertices.len() as u32;
+            let x = i as f32;
+            vertices.extend([
+                Position::from_values([x, 0., 0.]),
+                Position::from_values([x + 1., 0., 0.]),
+                Position::from_values([x + 1., 1., 0.]),
+                Position::from_values([x, 1., 0.]),
+            ]);
+            indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+
+        let ray = Ray::new(
+            &Position::from_values([40.75, 0.25, -1.]),
+            &Direction::from_values([0., 0., 1.]),
+        );
+        for quality in [BuildQuality::Fast, BuildQuality::High] {
+            let blas = BottomLevelAccelerationStructure::new(&vertices, &indices, quality);
+            assert_eq!(blas.bounding_box().unwrap().max()[0], 64.);
+
+            // Only the quad under the ray reports a hit.
+            let mut tested = 0;
+            let closest = blas.closest_hit(&ray, 0., f32::MAX, |primitive, t_max| {
+                tested += 1;
+                let t = 1. + (primitive / 2) as f32;
+                (primitive / 2 == 40 && t < t_max).then_some(t)
+            });
+            assert_eq!(closest, Some(41.));
+            assert!(tested < 8);
+
+            let single = BottomLevelAccelerationStructure::new(&vertices, &indices[..3], quality);
+            assert_eq!(
+                single.closest_hit(&ray, 0., f32::MAX, |_, _| Some(1.)),
+                None
+            );
+
+            let empty = BottomLevelAccelerationStructure::new(&vertices, &[], quality);
+            assert!(empty.bounding_box().is_none());
+            assert_eq!(empty.closest_hit(&ray, 0., f32::MAX, |_, _| Some(1.)), None);
+        }
+    }
+
+    #[test]
+    fn test_sah_far_from_origin() {
+        // Two clusters of triangles one float step apart along x, where a
+        // split position between them rounds back onto the first cluster.
+        let xs = [500000f32, f32::from_bits(500000f32.to_bits() + 1)];
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for x in xs {
+            for _ in 0..3 {
+                let base = vertices.len() as u32;
+                vertices.extend([
+                    Position::from_values([x, 0., 0.]),
+                    Position::from_values([x + 1., 1., 0.]),
+                    Position::from_values([x, 0., 1.]),
+                ]);
+                indices.extend([base, base + 1, base + 2]);
+            }
         }
+
+        let blas = BottomLevelAccelerationStructure::new(&vertices, &indices, BuildQuality::High);
+        let ray = Ray::new(
+            &Position::from_values([499999., 0.25, 0.25]),
+            &Direction::from_values([1., 0., 0.]),
+        );
+        let mut tested = Vec::new();
+        blas.closest_hit(&ray, 0., f32::MAX, |primitive, _| {
+            tested.push(primitive);
+            None
+        });
+        tested.sort();
+        assert_eq!(tested, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_deep_tree() {
+        // A chain of interior nodes whose near children are interior too, so
+        // every far leaf stays on the stack until the end of the chain.
+        let bounding_box = BoundingBox::new(Position::splat(-1.), Position::splat(1.));
+        let node = |offset: usize, primitive_count: u32| BvhNode {
+            bounding_box,
+            offset: offset as u32,
+            primitive_count,
+            axis: 0,
+        };
+        let chain = 2 * STACK_SIZE;
+        let mut nodes: Vec<BvhNode> = (0..chain).map(|i| node(2 * chain - i, 0)).collect();
+        nodes.extend((0..=chain).map(|_| node(0, 1)));
+        let bvh = Bvh {
+            depth: max_depth(&nodes),
+            nodes,
+            primitive_indices: vec![0],
+        };
+        assert_eq!(bvh.depth, chain);
+
+        let ray = Ray::new(&Position::new(), &Direction::from_values([1., 0., 0.]));
+        let mut tested = 0;
+        bvh.closest_hit(&ray, 0., f32::MAX, |_, _| {
+            tested += 1;
+            None
+        });
+        assert_eq!(tested, chain + 1);
+    }
+
+    #[test]
+    fn test_top_level() {
+        let mut resources = Resources::default();
+        let sphere = resources.add_hittable(Sphere::new(1., &Position::new()));
+        let material = DefaultKey::default();
+        let ray = Ray::new(
+            &Position::from_values([100., 100., 0.]),
+            &Direction::from_values([0., 0., -1.]),
+        );
+
+        let empty = TopLevelAccelerationStructure::new(resources.hittables(), &[]);
+        assert_eq!(empty.closest_hit(&ray, 0., f32::MAX, |_, _| Some(1.)), None);
+
+        // An empty mesh followed by a grid of spheres that does not contain
+        // the origin, the one under the ray is at z = -10.
+        let mesh = resources.add_hittable(TriangleMesh::new(
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+        ));
+        let mut instances = vec![Instance::new(mesh, 0, material, false)];
+        instances.extend((1..101).map(|i| {
+            let (x, y) = (
+                85. + 3. * ((i - 1) % 10) as f32,
+                85. + 3. * ((i - 1) / 10) as f32,
+            );
+            Instance::new(sphere, i, material, false).with_position(x, y, -10.)
+        }));
+        let scene = TopLevelAccelerationStructure::new(resources.hittables(), &instances);
+        let mut tested = 0;
+        let closest = scene.closest_hit(&ray, 0., f32::MAX, |id, t_max| {
+            tested += 1;
+            (id == 56 && 9. < t_max).then_some(9.)
+        });
+        assert_eq!(closest, Some(9.));
+        assert!(tested <= 4);
     }
 }
@@ -37,6 +37,11 @@ impl BoundingBox {
         length(&diff)
     }
 
+    pub fn surface_area(&self) -> f32 {
+        let d = self.dimensions();
+        2. * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
     pub fn center(&self) -> Position {
         let c = self.min + self.max;
         c * 0.5
@@ -56,11 +61,34 @@ impl BoundingBox {
         (*position - self.min()) / self.dimensions()
     }
 
+    // Bounds of all eight transformed corners, so rotations are covered too.
     pub fn transformed(&self, transform: &Transform) -> Self {
-        let min = *transform * &Vec4::from(self.min);
-        let max = *transform * &Vec4::from(self.max);
+        let mut result_min = Position::splat(f32::MAX);
+        let mut result_max = Position::splat(f32::MIN);
+        for corner in 0..8 {
+            let p = Position::from_values([
+                if corner & 1 == 0 {
+                    self.min[0]
+                } else {
+                    self.max[0]
+                },
+                if corner & 2 == 0 {
+                    self.min[1]
+                } else {
+                    self.max[1]
+                },
+                if corner & 4 == 0 {
+                    self.min[2]
+                } else {
+                    self.max[2]
+                },
+            ]);
+            let p = *transform * Vec4::from(p);
+            result_min = min(&result_min, &p);
+            result_max = max(&result_max, &p);
+        }
 
-        Self::new(min, max)
+        Self::new(result_min, result_max)
     }
 
     pub fn min(&self) -> &Position {
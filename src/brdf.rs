@@ -70,112 +70,253 @@ pub fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
     alpha / (PI * b * b)
 }
 
-pub fn sample_microfacet_transmission_brdf(
-    v: &Direction,
-    normal: &Direction,
-    albedo: &Color,
-    metal: f32,
-    roughness: f32,
-    fresnel: f32,
-    ior: f32,
-) -> (Direction, Color) {
+// Samples a microfacet normal around `normal` with a density of D(h) * n.h.
+pub fn sample_ggx_half_vector(normal: &Direction, roughness: f32) -> Direction {
     let x = float();
     let y = float();
 
-    let mut forward_normal = *normal;
-    let mut eta = 1.0 / ior;
-    if dot(v, normal) < 0.0 {
-        eta = ior;
-        forward_normal = -normal;
-    }
-
     let a = roughness * roughness;
-    let theta = (1.0 - y) / (1.0 + (a * a - 1.0) * y);
-    let theta = theta.sqrt().acos();
+    let cos_theta = ((1.0 - y) / (1.0 + (a * a - 1.0) * y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
     let phi = TAU * x;
-    let sin_theta = theta.sin();
-    let cos_theta = theta.cos();
-    let sin_phi = phi.sin();
-    let cos_phi = phi.cos();
-    let local_h = Direction::from_values([sin_theta * cos_phi, sin_theta * sin_phi, cos_theta]);
-    let h = normalize(&OrthoNormalBasis::from_w(&forward_normal).local(&local_h));
-    let l = refract_glsl(&-v, &h, eta);
-
-    let n_dot_v = saturate(dot(&forward_normal, v));
-    let n_dot_l = saturate(dot(&-forward_normal, &l));
-    let n_dot_h = saturate(dot(&forward_normal, &h));
-    let v_dot_h = saturate(dot(&v, &h));
-
-    let f0 = Color::splat(0.16 * (fresnel * fresnel));
-    let f0 = mix_vec3(&f0, albedo, metal);
+    let local_h = Direction::from_values([sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta]);
+    normalize(&OrthoNormalBasis::from_w(normal).local(&local_h))
+}
 
-    let f = fresnel_schlick(v_dot_h, &f0);
-    let g = g_smith(n_dot_v, n_dot_l, roughness);
-    let color = Color::ones() - f;
-    let color = *albedo * color * g * v_dot_h / (n_dot_h * n_dot_v).max(0.001);
-    (l, color)
+// Solid angle density of reflecting `v` into `l` about a microfacet normal
+// from `sample_ggx_half_vector`.
+pub fn pdf_ggx_reflection(v: &Direction, l: &Direction, normal: &Direction, roughness: f32) -> f32 {
+    let h = normalize(&(*v + *l));
+    let v_dot_h = dot(v, &h);
+    if v_dot_h <= 0.0 {
+        return 0.0;
+    }
+
+    let n_dot_h = saturate(dot(normal, &h));
+    distribution_ggx(n_dot_h, roughness) * n_dot_h / (4.0 * v_dot_h)
 }
 
-pub fn sample_diffuse_brdf(
-    v: &Direction,
-    albedo: &Color,
-    metal: f32,
-    fresnel: f32,
-) -> (Direction, Color) {
-    let x = float();
-    let y = float();
-    let theta = y.sqrt().asin();
-    let phi = TAU * x;
-    let sin_theta = theta.sin();
-    let cos_theta = theta.cos();
-    let sin_phi = phi.sin();
-    let cos_phi = phi.cos();
-    let l = Direction::from_values([sin_theta * cos_phi, sin_theta * sin_phi, cos_theta]);
-    let h = *v + l;
-    let v_dot_h = saturate(dot(v, &h));
-    let f0 = Color::splat(0.16 * fresnel * fresnel);
-    let f0 = mix_vec3(&f0, albedo, metal);
-    let f = fresnel_schlick(v_dot_h, &f0);
-    let not_specular = Color::ones() - f;
-    let diffuse = not_specular * albedo * (1.0 - metal);
-    (l, diffuse)
+// Fresnel reflectance of a dielectric for light at `cos_theta_i` to the
+// normal, `eta` being the index of refraction across the boundary over the
+// one on the side of the light. It is one under total internal reflection.
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.abs().min(1.0);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    let parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
 }
 
-pub fn sample_micro_facet_isotropic_specular_brdf(
-    v: &Direction,
+// Fresnel reflectance of a conductor with the complex index of refraction
+// `eta` + i`k` for light at `cos_theta_i` to the normal, after PBRT.
+pub fn fresnel_conductor(cos_theta_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2_theta = cos_theta_i.abs().min(1.0).powi(2);
+    let sin2_theta = 1.0 - cos2_theta;
+    let t0 = eta * eta - k * k - sin2_theta;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let t1 = a2_plus_b2 + cos2_theta;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta_i.abs() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2_theta * a2_plus_b2 + sin2_theta * sin2_theta;
+    let t4 = t2 * sin2_theta;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / 2.0
+}
+
+// Smith masking of the GGX distribution for a direction at `cos_theta` to
+// the normal.
+pub fn g1_ggx(cos_theta: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let cos2_theta = (cos_theta * cos_theta).max(1e-6);
+    let tan2_theta = (1.0 - cos2_theta) / cos2_theta;
+    2.0 / (1.0 + (1.0 + alpha * alpha * tan2_theta).sqrt())
+}
+
+// The microfacet normal that refracts `wo` into `wi`, if there is one.
+fn transmission_half_vector(
+    wo: &Direction,
+    wi: &Direction,
+    normal: &Direction,
+    eta: f32,
+) -> Option<Direction> {
+    let h = *wo + *wi * eta;
+    if length(&h) <= 1e-6 {
+        return None;
+    }
+
+    let h = normalize(&h);
+    let h = if dot(&h, normal) < 0.0 { -h } else { h };
+    if dot(wo, &h) <= 0.0 || dot(wi, &h) >= 0.0 {
+        return None;
+    }
+
+    Some(h)
+}
+
+// Rough dielectric transmission after Walter et al. 2007, "Microfacet Models
+// for Refraction through Rough Surfaces". `normal` faces `wo` and `eta` is the
+// index of refraction on the side of `wi` over the one on the side of `wo`.
+// The eta² of the BTDF cancels against the 1/eta² radiance picks up when it
+// crosses the boundary.
+pub fn evaluate_ggx_transmission(
+    wo: &Direction,
+    wi: &Direction,
     normal: &Direction,
-    albedo: &Color,
-    metal: f32,
     roughness: f32,
-    fresnel: f32,
-) -> (Direction, Color) {
-    let x = float();
-    let y = float();
+    eta: f32,
+) -> f32 {
+    let n_dot_v = dot(normal, wo);
+    let n_dot_l = dot(normal, wi);
+    if n_dot_v <= 0.0 || n_dot_l >= 0.0 {
+        return 0.0;
+    }
 
-    let a = roughness * roughness;
-    let theta = (1.0 - y) / (1.0 + (a * a - 1.0) * y);
-    let theta = theta.sqrt().acos();
-    let phi = TAU * x;
-    let sin_theta = theta.sin();
-    let cos_theta = theta.cos();
-    let sin_phi = phi.sin();
-    let cos_phi = phi.cos();
-    let local_h = Direction::from_values([sin_theta * cos_phi, sin_theta * sin_phi, cos_theta]);
-    let h = OrthoNormalBasis::from_w(normal).local(&local_h);
-    let l = reflect(&-v, &h);
+    let Some(h) = transmission_half_vector(wo, wi, normal, eta) else {
+        return 0.0;
+    };
 
-    let n_dot_v = saturate(dot(normal, v));
-    let n_dot_l = saturate(dot(normal, &l));
-    let n_dot_h = saturate(dot(normal, &h));
-    let v_dot_h = saturate(dot(&v, &h));
+    let v_dot_h = dot(wo, &h);
+    let l_dot_h = dot(wi, &h);
+    let denom = v_dot_h + eta * l_dot_h;
+    let d = distribution_ggx(dot(normal, &h), roughness);
+    let g = g1_ggx(n_dot_v, roughness) * g1_ggx(n_dot_l, roughness);
+    let f = fresnel_dielectric(v_dot_h, eta);
+    (1.0 - f) * d * g * (v_dot_h * l_dot_h).abs() / (n_dot_v * n_dot_l.abs() * denom * denom)
+}
 
-    let f0 = Color::splat(0.16 * fresnel * fresnel);
-    let f0 = mix_vec3(&f0, albedo, metal);
+// Solid angle density of `sample_ggx_transmission` returning `wi`.
+pub fn pdf_ggx_transmission(
+    wo: &Direction,
+    wi: &Direction,
+    normal: &Direction,
+    roughness: f32,
+    eta: f32,
+) -> f32 {
+    if dot(normal, wo) <= 0.0 || dot(normal, wi) >= 0.0 {
+        return 0.0;
+    }
 
-    let f = fresnel_schlick(v_dot_h, &f0);
-    let g = g_smith(n_dot_v, n_dot_l, roughness);
-    let specular = f * g * v_dot_h / (n_dot_h * n_dot_v).max(0.001);
-    (l, specular)
+    let Some(h) = transmission_half_vector(wo, wi, normal, eta) else {
+        return 0.0;
+    };
+
+    let n_dot_h = dot(normal, &h);
+    let l_dot_h = dot(wi, &h);
+    let denom = dot(wo, &h) + eta * l_dot_h;
+    distribution_ggx(n_dot_h, roughness) * n_dot_h * eta * eta * l_dot_h.abs() / (denom * denom)
+}
+
+// Refracts `wo` through the microfacet normal `h`. Returns None when the
+// light is totally reflected instead or doesn't make it through the surface.
+fn refract_through(
+    wo: &Direction,
+    h: &Direction,
+    normal: &Direction,
+    eta: f32,
+) -> Option<Direction> {
+    let wi = refract_glsl(&-wo, h, 1.0 / eta);
+    if dot(&wi, normal) >= 0.0 {
+        return None;
+    }
+
+    Some(normalize(&wi))
+}
+
+// Refracts `wo` through a microfacet normal from `sample_ggx_half_vector`.
+pub fn sample_ggx_transmission(
+    wo: &Direction,
+    normal: &Direction,
+    roughness: f32,
+    eta: f32,
+) -> Option<Direction> {
+    let h = sample_ggx_half_vector(normal, roughness);
+    if dot(wo, &h) <= 0.0 {
+        return None;
+    }
+
+    refract_through(wo, &h, normal, eta)
+}
+
+// A rough dielectric after Walter et al. 2007, which reflects with the
+// Fresnel reflectance of the microfacet and refracts otherwise, with total
+// internal reflection. `normal` faces `wo` and `eta` is the index of
+// refraction on the other side of the surface over the one on the side of
+// `wo`.
+pub fn evaluate_rough_dielectric(
+    wo: &Direction,
+    wi: &Direction,
+    normal: &Direction,
+    roughness: f32,
+    eta: f32,
+) -> f32 {
+    let n_dot_v = dot(normal, wo);
+    let n_dot_l = dot(normal, wi);
+    if n_dot_v <= 0.0 || n_dot_l == 0.0 {
+        return 0.0;
+    }
+
+    if n_dot_l < 0.0 {
+        return evaluate_ggx_transmission(wo, wi, normal, roughness, eta);
+    }
+
+    let h = normalize(&(*wo + *wi));
+    let f = fresnel_dielectric(dot(wo, &h), eta);
+    let d = distribution_ggx(dot(normal, &h), roughness);
+    let g = g1_ggx(n_dot_v, roughness) * g1_ggx(n_dot_l, roughness);
+    f * d * g / (4.0 * n_dot_v * n_dot_l)
+}
+
+// Solid angle density of `sample_rough_dielectric` returning `wi`.
+pub fn pdf_rough_dielectric(
+    wo: &Direction,
+    wi: &Direction,
+    normal: &Direction,
+    roughness: f32,
+    eta: f32,
+) -> f32 {
+    if dot(normal, wo) <= 0.0 {
+        return 0.0;
+    }
+
+    if dot(normal, wi) < 0.0 {
+        let Some(h) = transmission_half_vector(wo, wi, normal, eta) else {
+            return 0.0;
+        };
+
+        let transmittance = 1.0 - fresnel_dielectric(dot(wo, &h), eta);
+        return transmittance * pdf_ggx_transmission(wo, wi, normal, roughness, eta);
+    }
+
+    let h = normalize(&(*wo + *wi));
+    fresnel_dielectric(dot(wo, &h), eta) * pdf_ggx_reflection(wo, wi, normal, roughness)
+}
+
+// Reflects or refracts `wo` about a microfacet normal from
+// `sample_ggx_half_vector`, picking with the Fresnel reflectance.
+pub fn sample_rough_dielectric(
+    wo: &Direction,
+    normal: &Direction,
+    roughness: f32,
+    eta: f32,
+) -> Option<Direction> {
+    let h = sample_ggx_half_vector(normal, roughness);
+    let v_dot_h = dot(wo, &h);
+    if v_dot_h <= 0.0 {
+        return None;
+    }
+
+    if float() < fresnel_dielectric(v_dot_h, eta) {
+        let wi = reflect(&-wo, &h);
+        return (dot(&wi, normal) > 0.0).then_some(wi);
+    }
+
+    refract_through(wo, &h, normal, eta)
 }
 
 pub fn evaluate_microfacet_isotropic_brdf(
@@ -185,16 +326,16 @@ pub fn evaluate_microfacet_isotropic_brdf(
     albedo: &Color,
     metal: f32,
     roughness: f32,
-    transmission: f32,
+    fresnel: f32,
 ) -> Color {
     let h = *l + *v;
     let h = normalize(&h);
-    let n_dot_v = saturate(dot(&normal, v));
-    let n_dot_l = saturate(dot(&normal, l));
-    let n_dot_h = saturate(dot(&normal, &h));
-    let v_dot_h = saturate(dot(&normal, v));
+    let n_dot_v = saturate(dot(normal, v));
+    let n_dot_l = saturate(dot(normal, l));
+    let n_dot_h = saturate(dot(normal, &h));
+    let v_dot_h = saturate(dot(v, &h));
 
-    let f0 = Color::splat(0.04);
+    let f0 = Color::splat(0.16 * fresnel * fresnel);
     let f0 = mix_vec3(&f0, albedo, metal);
 
     let f = fresnel_schlick(v_dot_h, &f0);
@@ -203,7 +344,7 @@ pub fn evaluate_microfacet_isotropic_brdf(
     let specular = (d * g * f) / (4.0 * n_dot_v * n_dot_l).max(0.001);
 
     let mut not_specular = Color::ones() - f;
-    not_specular *= (1.0 - metal) * (1.0 - transmission);
+    not_specular *= 1.0 - metal;
     let diffuse = not_specular * albedo / PI;
     diffuse + specular
 }
@@ -217,6 +358,42 @@ fn test_reflectance() {
     assert_eq!(actual, expected);
 }
 
+#[test]
+fn test_rough_dielectric() {
+    // Once the eta² scaling of refracted radiance is undone, a rough
+    // dielectric keeps nearly all the energy from either side, past the
+    // critical angle included.
+    let normal = Direction::from_values([0.0, 0.0, 1.0]);
+    let roughness = 0.3;
+    let samples = 20000;
+    for eta in [1.5, 1.0 / 1.5] {
+        for slope in [0.5, 2.0] {
+            let wo = normalize(&Direction::from_values([slope, 0.0, 1.0]));
+            let mut energy = 0.0;
+            for _ in 0..samples {
+                let Some(wi) = sample_rough_dielectric(&wo, &normal, roughness, eta) else {
+                    continue;
+                };
+
+                let pdf = pdf_rough_dielectric(&wo, &wi, &normal, roughness, eta);
+                let f = evaluate_rough_dielectric(&wo, &wi, &normal, roughness, eta);
+                let cos_theta = dot(&wi, &normal);
+                let scale = if cos_theta < 0.0 { eta * eta } else { 1.0 };
+                energy += f * cos_theta.abs() * scale / pdf;
+            }
+
+            let energy = energy / samples as f32;
+            assert!(
+                energy > 0.9 && energy < 1.02,
+                "{} {} {}",
+                eta,
+                slope,
+                energy
+            );
+        }
+    }
+}
+
 #[test]
 fn test_refract() {
     let uv = Direction::from_values([1.0, 1.0, 0.0]);
@@ -1,18 +1,26 @@
+use crate::film::{Aovs, Film};
 use crate::light::Lights;
+use crate::material::HitRecord;
 
 use super::acceleration_structure::*;
 use super::intersection::*;
+use super::rand;
 use super::ray::*;
 use super::raytracer::*;
 use super::resources::Resources;
+use super::shader_binding_table::{Payload, ShaderBindingTable};
 use super::types::Color;
-use super::vec::{XAccessor, YAccessor, ZAccessor};
+use super::vec::length;
 
-use image::*;
 use rayon::prelude::IntoParallelIterator;
 use rayon::prelude::ParallelIterator;
+
 pub struct CPUTracer {
     ray_generation_shader: Box<dyn RayGenerationShader>,
+    shader_binding_table: ShaderBindingTable,
+    seed: Option<u64>,
+    aovs: bool,
+    alpha: bool,
 }
 
 impl CPUTracer {
@@ -22,8 +30,74 @@ impl CPUTracer {
     {
         Self {
             ray_generation_shader: Box::new(ray_generation_shader),
+            shader_binding_table: ShaderBindingTable::default(),
+            seed: None,
+            aovs: false,
+            alpha: false,
         }
     }
+
+    // Replaces the built in path tracing and sky shaders. Fails when the
+    // table has no hit group or miss shader to fall back to.
+    pub fn with_shader_binding_table(
+        mut self,
+        shader_binding_table: ShaderBindingTable,
+    ) -> Result<Self, String> {
+        shader_binding_table.validate()?;
+        self.shader_binding_table = shader_binding_table;
+        Ok(self)
+    }
+
+    // Every pixel gets its own generator derived from the seed, so renders are
+    // reproducible regardless of how rayon schedules the work.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Also render albedo, normal and depth layers of the first hit.
+    pub fn with_aovs(mut self) -> Self {
+        self.aovs = true;
+        self
+    }
+
+    // Keep the alpha the ray generation shader returns, for transparent
+    // backgrounds.
+    pub fn with_alpha(mut self) -> Self {
+        self.alpha = true;
+        self
+    }
+
+    fn first_hit_aovs(
+        &self,
+        scene: &TopLevelAccelerationStructure,
+        resources: &Resources,
+        width: u32,
+        height: u32,
+        x: u32,
+        y: u32,
+    ) -> Option<Aovs> {
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = (y as f32 + 0.5) / height as f32;
+        let ray = self.ray_generation_shader.primary_ray(u, 1. - v)?;
+        let mut aovs = Aovs::default();
+        if let Some((instance_id, hit)) = self.intersect(&ray, scene, resources, 0.001, 1000.0) {
+            let instance = scene.instance(instance_id as usize);
+            let geometry = resources.hittable(instance.geometry_index);
+            let hit_record = HitRecord::new(instance, geometry, &hit);
+
+            let material_id = geometry
+                .material(&hit_record.intersection)
+                .unwrap_or(instance.material_id);
+            aovs.albedo = resources
+                .material(material_id)
+                .albedo(resources, &hit_record);
+            aovs.normal = hit_record.normal;
+            aovs.depth = hit_record.intersection.t * length(ray.direction());
+        }
+
+        Some(aovs)
+    }
 }
 
 unsafe impl Send for CPUTracer {}
@@ -32,35 +106,55 @@ unsafe impl Sync for CPUTracer {}
 impl RayTracer for CPUTracer {
     fn trace(
         &self,
-        spp: u32,
-        max_depth: u32,
-        width: u32,
-        height: u32,
+        settings: &TraceSettings,
         scene: &TopLevelAccelerationStructure,
         lights: &Lights,
         resources: &Resources,
-    ) {
-        let mut image = RgbImage::new(width, height);
-        (0..height).into_iter().for_each(|y| {
-            let row: Vec<Rgb<u8>> = (0..width)
+    ) -> Film {
+        let TraceSettings {
+            width, height, spp, ..
+        } = *settings;
+        let mut film = Film::new(width, height);
+        if self.aovs {
+            film = film.with_aovs();
+        }
+        if self.alpha {
+            film = film.with_alpha();
+        }
+
+        (0..height).for_each(|y| {
+            let row: Vec<(Color, f32, Option<Aovs>)> = (0..width)
                 .into_par_iter()
                 .map(|x| {
-                    let color = self.ray_generation_shader.generate(
-                        self, scene, lights, resources, spp, max_depth, width, height, x, y,
-                    );
-                    let c = color / (Color::from_values([1.0, 1.0, 1.0]) + color);
-                    let r = (c.x().sqrt() * 255.) as u8;
-                    let g = (c.y().sqrt() * 255.) as u8;
-                    let b = (c.z().sqrt() * 255.) as u8;
-                    Rgb([r, g, b])
+                    if let Some(seed) = self.seed {
+                        rand::seed(seed ^ ((y as u64) << 32 | x as u64));
+                    }
+                    let context = ShaderContext {
+                        ray_tracer: self,
+                        scene,
+                        lights,
+                        resources,
+                    };
+                    let (color, alpha) = self
+                        .ray_generation_shader
+                        .generate(&context, settings, x, y);
+                    let aovs = if self.aovs {
+                        self.first_hit_aovs(scene, resources, width, height, x, y)
+                    } else {
+                        None
+                    };
+                    (color, alpha, aovs)
                 })
                 .collect();
-            for i in 0..width {
-                image.put_pixel(i, y, row[i as usize])
+            for (x, (color, alpha, aovs)) in row.iter().enumerate() {
+                film.add_samples_with_alpha(x as u32, y, color, *alpha, spp);
+                if let Some(aovs) = aovs {
+                    film.set_aovs(x as u32, y, aovs);
+                }
             }
         });
 
-        image.save("output.png").expect("Write to image failed");
+        film
     }
 
     fn intersect(
@@ -71,29 +165,102 @@ impl RayTracer for CPUTracer {
         t_min: f32,
         t_max: f32,
     ) -> Option<(u32, Intersection)> {
-        let results = scene.intersect_instance(ray, t_min, t_max);
-        if results.len() > 0 {
-            let mut closest = None;
-            let mut t: f32 = 1001.;
-            for id in results.iter() {
-                let instance = scene.instance(*id as usize);
-                if let Some(intersection) = resources.hittable(instance.geometry_index).intersect(
-                    &instance.transform,
-                    ray,
+        let mut closest = None;
+        scene.closest_hit(ray, t_min, t_max, |id, t_max| {
+            let instance = scene.instance(id as usize);
+            let object_ray = ray.transformed(&instance.world_to_object);
+            let geometry = resources.hittable(instance.geometry_index);
+            let intersection = match &self
+                .shader_binding_table
+                .hit_group(instance.hit_shader_id)
+                .any_hit
+            {
+                Some(any_hit) => geometry.intersect_with(
+                    &object_ray,
                     instance.cull,
                     t_min,
                     t_max,
-                ) {
-                    if intersection.t >= t_min && intersection.t < t_max && intersection.t < t {
-                        t = intersection.t;
-                        closest = Some((instance.instance_id, intersection));
-                    }
-                }
-            }
+                    &mut |intersection| {
+                        any_hit.any_hit(
+                            resources,
+                            instance.instance_id,
+                            &intersection.with_ray(ray),
+                        )
+                    },
+                ),
+                None => geometry.intersect(&object_ray, instance.cull, t_min, t_max),
+            };
+            let intersection = intersection
+                .filter(|intersection| intersection.t >= t_min && intersection.t < t_max)?;
 
-            return closest;
-        }
+            // t is the same in both spaces, only the ray goes back.
+            let t = intersection.t;
+            closest = Some((instance.instance_id, intersection.with_ray(ray)));
+            Some(t)
+        });
+
+        closest
+    }
+
+    fn occluded(
+        &self,
+        ray: &Ray,
+        scene: &TopLevelAccelerationStructure,
+        resources: &Resources,
+        t_min: f32,
+        t_max: f32,
+    ) -> bool {
+        scene.any_hit(ray, t_min, t_max, |id, t_max| {
+            let instance = scene.instance(id as usize);
+            let object_ray = ray.transformed(&instance.world_to_object);
+            let geometry = resources.hittable(instance.geometry_index);
+            let occluded = match &self
+                .shader_binding_table
+                .hit_group(instance.hit_shader_id)
+                .any_hit
+            {
+                Some(any_hit) => geometry
+                    .intersect_with(
+                        &object_ray,
+                        instance.cull,
+                        t_min,
+                        t_max,
+                        &mut |intersection| {
+                            any_hit.any_hit(
+                                resources,
+                                instance.instance_id,
+                                &intersection.with_ray(ray),
+                            )
+                        },
+                    )
+                    .is_some(),
+                None => geometry.occluded(&object_ray, instance.cull, t_min, t_max),
+            };
+            occluded.then_some(t_max)
+        })
+    }
 
-        None
+    fn trace_ray(
+        &self,
+        context: &ShaderContext,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        miss_index: u32,
+        payload: &mut Payload,
+    ) {
+        match self.intersect(ray, context.scene, context.resources, t_min, t_max) {
+            Some((instance_id, intersection)) => {
+                let instance = context.scene.instance(instance_id as usize);
+                self.shader_binding_table
+                    .hit_group(instance.hit_shader_id)
+                    .closest_hit
+                    .hit(context, instance_id, &intersection, payload)
+            }
+            None => self
+                .shader_binding_table
+                .miss_shader(miss_index)
+                .miss(context, ray, payload),
+        }
     }
 }
@@ -3,7 +3,7 @@ use super::types::*;
 use crate::vec::XAccessor;
 use crate::vec::YAccessor;
 use crate::{
-    degrees_to_radians,
+    math_utils::degrees_to_radians,
     ray::Ray,
     vec::{cross, normalize},
 };
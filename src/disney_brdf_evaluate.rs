@@ -1,12 +1,66 @@
+use crate::brdf::evaluate_ggx_transmission;
 use crate::brdf::saturate;
-use crate::math_utils::{mix, mix_vec3, pow2, same_hemisphere};
+use crate::math_utils::{mix, mix_vec3, pow2};
 use crate::onb::OrthoNormalBasis;
-use crate::types::{Color, Direction, Vec3};
+use crate::types::{Color, Direction};
 
 use super::rand;
 use super::vec::*;
 use std::f32::consts::PI;
 
+// The parameters of Disney's principled BSDF at a point of a surface, after
+// Burley 2012 and 2015. All of them but the base color are in [0, 1].
+#[derive(Clone, Copy, Debug)]
+pub struct DisneyParameters {
+    pub base_color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub anisotropic: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub clearcoat: f32,
+    pub clearcoat_gloss: f32,
+    pub specular_tint: f32,
+    pub subsurface: f32,
+    pub spec_trans: f32,
+    // Index of refraction behind the surface over the one in front of it, as
+    // seen from `wo`.
+    pub eta: f32,
+}
+
+impl DisneyParameters {
+    // GGX roughness along the two tangents.
+    pub fn alphas(&self) -> (f32, f32) {
+        anisotropic_alphas(self.roughness, self.anisotropic)
+    }
+
+    // Reflectance at normal incidence. Dielectrics get it from the index of
+    // refraction, metals from their base color.
+    pub fn specular_color(&self) -> Color {
+        let f0 = pow2((self.eta - 1.) / (self.eta + 1.));
+        let tint = mix_vec3(&Color::ones(), &hue(&self.base_color), self.specular_tint);
+        mix_vec3(&(f0 * tint), &self.base_color, self.metallic)
+    }
+}
+
+// GGX roughness along the two tangents, with Disney's mapping of roughness
+// and anisotropy.
+pub fn anisotropic_alphas(roughness: f32, anisotropic: f32) -> (f32, f32) {
+    let aspect = (1. - anisotropic * 0.9).sqrt();
+    let alpha = pow2(roughness);
+    ((alpha / aspect).max(0.001), (alpha * aspect).max(0.001))
+}
+
+// The base color normalized by its luminance, to isolate hue and saturation.
+fn hue(base_color: &Color) -> Color {
+    let luminance = 0.3 * base_color.r() + 0.6 * base_color.g() + 0.1 * base_color.b();
+    if luminance > 0. {
+        *base_color / luminance
+    } else {
+        Color::ones()
+    }
+}
+
 pub fn schlick_weight(cos_theta: f32) -> f32 {
     let m = saturate(1. - cos_theta);
     return (m * m) * (m * m) * m;
@@ -61,14 +115,14 @@ pub fn ggx_micro_facet_normal(roughness: f32, hit_normal: &Direction) -> Directi
     let sin_theta = (1.0 - cos_theta - cos_theta).max(0.0).sqrt();
     let phi = r2 * PI * 2.0;
 
-    let onb = OrthoNormalBasis::from_w(&hit_normal);
+    let onb = OrthoNormalBasis::from_w(hit_normal);
     onb.u() * (sin_theta * phi.cos()) + onb.v() * (sin_theta * phi.sin()) + onb.w() * cos_theta
 }
 
 pub fn smith_g_ggx(n_dot_v: f32, roughness: f32) -> f32 {
     let a = pow2(roughness);
     let b = pow2(n_dot_v);
-    1.0 / n_dot_v.abs() + (a + b - a * b).sqrt().max(0.001)
+    1.0 / (n_dot_v.abs() + (a + b - a * b).sqrt()).max(0.001)
 }
 
 pub fn smith_g_ggx_aniso(n_dot_v: f32, v_dot_x: f32, v_dot_y: f32, ax: f32, ay: f32) -> f32 {
@@ -80,10 +134,8 @@ pub fn smith_g_ggx_aniso(n_dot_v: f32, v_dot_x: f32, v_dot_y: f32, ax: f32, ay:
 }
 
 pub fn direction_of_anisotropicity(normal: &Direction) -> (Direction, Direction) {
-    let tangent = cross(normal, &Direction::from_values([1., 0., 1.]));
-    let binormal = normalize(&cross(normal, &tangent));
-    let tangent = normalize(&cross(normal, &binormal));
-    (tangent, binormal)
+    let onb = OrthoNormalBasis::from_w(normal);
+    (*onb.u(), *onb.v())
 }
 
 pub fn evaluate_disney_diffuse(
@@ -143,148 +195,107 @@ pub fn evaluate_disney_sheen(
     sheen_tint: f32,
 ) -> Color {
     let fh = schlick_weight(l_dot_h);
-    let c_lum = 0.3 * base_color.r() + 0.6 * base_color.g() * 0.1 * base_color.b();
-    let c_tint = if c_lum > 0. {
-        *base_color / c_lum
-    } else {
-        Color::ones()
-    };
-
-    let c_sheen = mix_vec3(&Color::ones(), &c_tint, sheen_tint);
+    let c_sheen = mix_vec3(&Color::ones(), &hue(base_color), sheen_tint);
     fh * sheen * c_sheen
 }
 
 pub fn evaluate_disney_anisotropic_specular(
-    n_dot_l: f32,
-    n_dot_v: f32,
-    n_dot_h: f32,
-    l_dot_h: f32,
     l: &Direction,
     v: &Direction,
     h: &Direction,
+    normal: &Direction,
     x: &Direction,
     y: &Direction,
-    base_color: &Color,
-    roughness: f32,
-    metal: f32,
-    anisotropy: f32,
+    parameters: &DisneyParameters,
 ) -> Color {
-    let cdlum = 0.3 * base_color.x() + 0.6 * base_color.y() + 0.1 * base_color.z(); // luminance approx.
-
-    let ctint = if cdlum > 0f32 {
-        base_color * 1.0 / cdlum
-    } else {
-        Vec3::from_values([1., 1., 1.])
-    }; // normalize lum. to isolate hue+sat
-    let specular = 0.0;
-    let specular_tint = 0.0;
-    let tint = mix_vec3(&Vec3::from_values([1., 1., 1.]), &ctint, specular_tint);
-    let cspec0 = mix_vec3(&(specular * 0.08 * tint), base_color, metal);
-
-    let roughness_2 = roughness * roughness;
-
-    let aspect = (1. - anisotropy * 0.9).sqrt();
-    let ax = (roughness_2 / aspect).max(0.001);
-    let ay = (roughness_2 * aspect).max(0.001);
+    let n_dot_l = dot(normal, l);
+    let n_dot_v = dot(normal, v);
+    let n_dot_h = dot(normal, h);
+    let l_dot_h = dot(l, h);
+    let (ax, ay) = parameters.alphas();
     let ds = gtr2_anisotropic(n_dot_h, dot(h, x), dot(h, y), ax, ay);
     let fh = schlick_weight(l_dot_h);
-    let fs = mix_vec3(&cspec0, &Vec3::from_values([1., 1., 1.]), fh);
+    let fs = mix_vec3(&parameters.specular_color(), &Color::ones(), fh);
     let gs = smith_g_ggx_aniso(n_dot_l, dot(l, x), dot(l, y), ax, ay)
         * smith_g_ggx_aniso(n_dot_v, dot(v, x), dot(v, y), ax, ay);
 
-    return gs * fs * ds;
+    gs * fs * ds
 }
 
-pub fn evaluate_disney_isotropic_specular() {}
-
-pub fn evaluate_disney_micro_facet_anisotropic(
-    wi: &Direction,
-    wo: &Direction,
-    normal: &Direction,
-    x: &Direction,
-    y: &Direction,
-    base_color: &Color,
-    roughness: f32,
-    metal: f32,
-    anisotropy: f32,
+// The specular lobe without anisotropy, which doesn't need the tangents.
+pub fn evaluate_disney_isotropic_specular(
+    n_dot_l: f32,
+    n_dot_v: f32,
+    n_dot_h: f32,
+    l_dot_h: f32,
+    parameters: &DisneyParameters,
 ) -> Color {
-    if !same_hemisphere(wo, wi, normal) {
-        return Color::new();
-    }
-
-    let n_dot_l = dot(normal, wo);
-    let n_dot_v = dot(normal, wi);
-
-    if n_dot_l < 0. || n_dot_v < 0. {
-        return Color::new();
-    };
-
-    let h = *wo + *wi;
-    let h = normalize(&h);
-    let n_dot_h = dot(normal, &h);
-    let l_dot_h = dot(wo, &h);
-
-    let diffuse = evaluate_disney_diffuse(n_dot_l, n_dot_v, l_dot_h, base_color, roughness);
-    let specular = evaluate_disney_anisotropic_specular(
-        n_dot_l, n_dot_v, n_dot_h, l_dot_h, wi, wo, &h, x, y, base_color, roughness, metal,
-        anisotropy,
-    );
+    let alpha = pow2(parameters.roughness).max(0.001);
+    let ds = gtr2(n_dot_h, alpha);
+    let fh = schlick_weight(l_dot_h);
+    let fs = mix_vec3(&parameters.specular_color(), &Color::ones(), fh);
+    let gs = smith_g_ggx(n_dot_l, alpha) * smith_g_ggx(n_dot_v, alpha);
 
-    diffuse * (1.0 - metal) + specular
+    gs * fs * ds
 }
 
+// The BSDF for light arriving from `wi` and leaving towards `wo`, `normal`
+// facing `wo` and `x` and `y` being the tangents of the anisotropy. Light
+// from behind the surface only gets through the specular transmission lobe.
 pub fn evaluate_disney_bsdf(
     wi: &Direction,
     wo: &Direction,
     normal: &Direction,
     x: &Direction,
     y: &Direction,
-    base_color: &Color,
-    roughness: f32,
-    metal: f32,
-    sheen: f32,
-    sheen_tint: f32,
-    clear_coat: f32,
-    clear_coat_boost: f32,
-    clear_coat_gloss: f32,
-    anisotropy: f32,
-    sub_surface: f32,
+    parameters: &DisneyParameters,
 ) -> Color {
-    if !same_hemisphere(wo, wi, normal) {
+    let n_dot_l = dot(normal, wi);
+    let n_dot_v = dot(normal, wo);
+    if n_dot_v <= 0.0 || n_dot_l == 0.0 {
         return Color::new();
     }
 
-    let n_dot_l = dot(&normal, wo);
-    let n_dot_v = dot(&normal, wi);
+    let dielectric = 1.0 - parameters.metallic;
+    if n_dot_l < 0.0 {
+        let transmission = dielectric * parameters.spec_trans;
+        if transmission <= 0.0 {
+            return Color::new();
+        }
 
-    if n_dot_l < 0.0 || n_dot_v < 0.0 {
-        return Color::new();
+        let tint = Color::from_values(parameters.base_color.data.map(f32::sqrt));
+        let btdf = evaluate_ggx_transmission(wo, wi, normal, parameters.roughness, parameters.eta);
+        return tint * (transmission * btdf);
     }
-    let h = *wi + *wo;
-    let h = normalize(&h);
+
+    let h = normalize(&(*wi + *wo));
     let n_dot_h = dot(normal, &h);
-    let l_dot_h = dot(wo, &h);
+    let l_dot_h = dot(wi, &h);
+    let base_color = &parameters.base_color;
 
-    let diffuse_brdf = evaluate_disney_diffuse(n_dot_l, n_dot_v, l_dot_h, base_color, roughness);
+    let diffuse_brdf =
+        evaluate_disney_diffuse(n_dot_l, n_dot_v, l_dot_h, base_color, parameters.roughness);
     let sub_surface_brdf =
-        evaluate_disney_subsurface(n_dot_l, n_dot_v, l_dot_h, base_color, roughness);
-    let gloss_brdf = evaluate_disney_anisotropic_specular(
-        n_dot_l, n_dot_v, n_dot_h, l_dot_h, wi, wo, &h, x, y, base_color, roughness, metal,
-        anisotropy,
-    );
+        evaluate_disney_subsurface(n_dot_l, n_dot_v, l_dot_h, base_color, parameters.roughness);
+    let sheen_brdf =
+        evaluate_disney_sheen(l_dot_h, base_color, parameters.sheen, parameters.sheen_tint);
+    let kd = mix_vec3(&diffuse_brdf, &sub_surface_brdf, parameters.subsurface) + sheen_brdf;
+    let kd = kd * (dielectric * (1.0 - parameters.spec_trans));
+
+    let gloss_brdf = if parameters.anisotropic > 0.0 {
+        evaluate_disney_anisotropic_specular(wi, wo, &h, normal, x, y, parameters)
+    } else {
+        evaluate_disney_isotropic_specular(n_dot_l, n_dot_v, n_dot_h, l_dot_h, parameters)
+    };
     let clear_coat_brdf = evaluate_disney_clear_coat(
         n_dot_l,
         n_dot_v,
         n_dot_h,
         l_dot_h,
-        clear_coat,
-        clear_coat_gloss,
-        clear_coat_boost,
+        parameters.clearcoat,
+        parameters.clearcoat_gloss,
+        0.25,
     );
-    let sheen_brdf = evaluate_disney_sheen(l_dot_h, base_color, sheen, sheen_tint);
 
-    let kd = mix_vec3(&diffuse_brdf, &sub_surface_brdf, sub_surface) + sheen_brdf;
-    let kd = kd * (1.0 - metal);
-    let theta = dot(normal, wi).max(0.001);
-    (kd) // + gloss_brdf + clear_coat_brdf) * theta
+    kd + gloss_brdf + Color::splat(clear_coat_brdf)
 }
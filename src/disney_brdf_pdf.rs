@@ -1,7 +1,8 @@
 use std::f32::consts::PI;
 
 use crate::{
-    disney_brdf_evaluate::gtr1,
+    brdf::pdf_ggx_transmission,
+    disney_brdf_evaluate::{gtr1, DisneyParameters},
     math_utils::{mix, pow2, same_hemisphere},
     vec::{dot, normalize},
 };
@@ -28,9 +29,14 @@ pub fn pdf_clear_coat(
 
     let wh = *wi + *wo;
     let wh = normalize(&wh);
+    let o_dot_h = dot(wo, &wh).abs();
+    if o_dot_h == 0. {
+        return 0f32;
+    }
+
     let n_dot_h = dot(&wh, normal).abs();
     let dr = gtr1(n_dot_h, mix(0.1, 0.001, clear_coat_gloss));
-    dr * n_dot_h * (4.0 * dot(wo, &wh))
+    dr * n_dot_h / (4.0 * o_dot_h)
 }
 
 pub fn pdf_disney_micro_facet_anisotropic(
@@ -59,28 +65,56 @@ pub fn pdf_disney_micro_facet_anisotropic(
     let h_dot_y = dot(&wh, y);
     let n_dot_h = dot(normal, &wh);
 
+    let o_dot_h = dot(wo, &wh);
     let denom = h_dot_x * h_dot_x / alpha_x_2 + h_dot_y * h_dot_y / alpha_y_2 + pow2(n_dot_h);
-    if denom == 0. {
+    if denom == 0. || o_dot_h <= 0. {
         return 0f32;
     }
 
     let pdf_distribution = n_dot_h / (PI * alpha_x * alpha_y * pow2(denom));
-    pdf_distribution / (4. * dot(wo, &wh))
+    pdf_distribution / (4. * o_dot_h)
+}
+
+// Chance that `sample_disney_bsdf` picks the diffuse, the specular, the
+// clearcoat or the transmission lobe.
+pub fn disney_lobe_probabilities(parameters: &DisneyParameters) -> [f32; 4] {
+    let dielectric = 1.0 - parameters.metallic;
+    let weights = [
+        dielectric * (1.0 - parameters.spec_trans),
+        1.0,
+        0.25 * parameters.clearcoat,
+        dielectric * parameters.spec_trans,
+    ];
+    let total: f32 = weights.iter().sum();
+    weights.map(|weight| weight / total)
 }
 
+// Solid angle density of `sample_disney_bsdf` returning `wi`, `normal`
+// facing `wo`.
 pub fn pdf_disney(
     wi: &Direction,
     wo: &Direction,
     normal: &Direction,
     x: &Direction,
     y: &Direction,
-    roughness: f32,
-    anisotropy: f32,
+    parameters: &DisneyParameters,
 ) -> f32 {
-    let pdf_diffuse = pdf_lambert(wi, wo, normal);
-    let pdf_specular =
-        pdf_disney_micro_facet_anisotropic(wi, wo, normal, x, y, roughness, anisotropy);
-    let total = pdf_diffuse + pdf_specular;
-    total * 0.5;
-    pdf_diffuse
+    let [diffuse, specular, clear_coat, transmission] = disney_lobe_probabilities(parameters);
+    if dot(normal, wi) < 0.0 {
+        return transmission
+            * pdf_ggx_transmission(wo, wi, normal, parameters.roughness, parameters.eta);
+    }
+
+    diffuse * pdf_lambert(wi, wo, normal)
+        + specular
+            * pdf_disney_micro_facet_anisotropic(
+                wi,
+                wo,
+                normal,
+                x,
+                y,
+                parameters.roughness,
+                parameters.anisotropic,
+            )
+        + clear_coat * pdf_clear_coat(wi, wo, normal, parameters.clearcoat_gloss)
 }
@@ -3,25 +3,26 @@ use std::f32::consts::TAU;
 
 use super::rand;
 use super::types::*;
-use crate::disney_brdf_pdf::pdf_disney;
-use crate::disney_brdf_pdf::pdf_lambert;
+use crate::brdf::sample_ggx_transmission;
+use crate::disney_brdf_evaluate::DisneyParameters;
+use crate::disney_brdf_pdf::disney_lobe_probabilities;
+use crate::material::Lobe;
 use crate::onb::OrthoNormalBasis;
 use crate::rand::cosine;
 use crate::vec::dot;
 use crate::{
-    disney_brdf_pdf::pdf_disney_micro_facet_anisotropic,
-    math_utils::same_hemisphere,
+    math_utils::{mix, same_hemisphere},
     vec::{normalize, reflect, XAccessor, YAccessor, ZAccessor},
 };
 
 pub fn sample_disney_diffuse(wo: &Direction, normal: &Direction) -> Direction {
     let local = cosine();
-    let mut wi = OrthoNormalBasis::from_w(normal).local(&local);
-    if dot(&wo, normal) < 0.0 {
-        wi.data[2] *= -1.0
+    let wi = OrthoNormalBasis::from_w(normal).local(&local);
+    if dot(wo, normal) < 0.0 {
+        -wi
+    } else {
+        wi
     }
-
-    wi
 }
 
 pub fn sample_disney_sub_surface(wo: &Direction, normal: &Direction) -> Direction {
@@ -34,6 +35,30 @@ pub fn sample_disney_sub_surface(wo: &Direction, normal: &Direction) -> Directio
     wi
 }
 
+// Reflects `wo` about a microfacet normal drawn from the GTR1 distribution of
+// the clearcoat.
+pub fn sample_disney_clear_coat(
+    wo: &Direction,
+    normal: &Direction,
+    clear_coat_gloss: f32,
+) -> Direction {
+    let a = mix(0.1, 0.001, clear_coat_gloss);
+    let a2 = a * a;
+    let u = rand::float();
+    let cos_theta = ((1. - a2.powf(1. - u)) / (1. - a2)).max(0.0).sqrt();
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = TAU * rand::float();
+
+    let wh_local =
+        Direction::from_values([sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta]);
+    let mut wh = OrthoNormalBasis::from_w(normal).local(&wh_local);
+    if !same_hemisphere(wo, &wh, normal) {
+        wh *= -1.;
+    }
+
+    normalize(&reflect(&-wo, &wh))
+}
+
 pub fn sample_disney_micro_facet_anisotropic(
     wo: &Direction,
     normal: &Direction,
@@ -70,24 +95,45 @@ pub fn sample_disney_micro_facet_anisotropic(
         wh *= -1.;
     }
 
-    let wi = normalize(&reflect(&-wo, &wh));
-    wi
+    normalize(&reflect(&-wo, &wh))
 }
 
+// Picks a lobe with the chances of `disney_lobe_probabilities` and samples
+// it, `normal` facing `wo`. Returns None when the sampled direction leaves
+// through the wrong side of the surface.
 pub fn sample_disney_bsdf(
     wo: &Direction,
     normal: &Direction,
     x: &Direction,
     y: &Direction,
-    roughness: f32,
-    anisotropy: f32,
-) -> (Direction, f32) {
+    parameters: &DisneyParameters,
+) -> Option<(Direction, Lobe)> {
+    let [diffuse, specular, clear_coat, _] = disney_lobe_probabilities(parameters);
     let r = rand::float();
-    // let wi = if r < 0.5 {
-    let wi = sample_disney_diffuse(wo, normal);
-    // } else {
-    //     sample_disney_micro_facet_anisotropic(wo, normal, x, y, roughness, anisotropy)
-    // };
+    let (wi, lobe) = if r < diffuse {
+        let wi = sample_disney_diffuse(wo, normal);
+        (wi, Lobe::DIFFUSE | Lobe::REFLECTION)
+    } else if r < diffuse + specular {
+        let wi = sample_disney_micro_facet_anisotropic(
+            wo,
+            normal,
+            x,
+            y,
+            parameters.roughness,
+            parameters.anisotropic,
+        );
+        (wi, Lobe::GLOSSY | Lobe::REFLECTION)
+    } else if r < diffuse + specular + clear_coat {
+        let wi = sample_disney_clear_coat(wo, normal, parameters.clearcoat_gloss);
+        (wi, Lobe::GLOSSY | Lobe::REFLECTION)
+    } else {
+        let wi = sample_ggx_transmission(wo, normal, parameters.roughness, parameters.eta)?;
+        return Some((wi, Lobe::GLOSSY | Lobe::TRANSMISSION));
+    };
+
+    if dot(&wi, normal) <= 0.0 {
+        return None;
+    }
 
-    (wi, pdf_disney(&wi, wo, normal, x, y, roughness, anisotropy))
+    Some((wi, lobe))
 }
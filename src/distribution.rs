@@ -0,0 +1,150 @@
+// Piecewise constant distributions for importance sampling tabulated
+// functions, after PBRT.
+pub struct Distribution1D {
+    function: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    // Negative values are treated as zero. A function that is zero everywhere
+    // is sampled uniformly.
+    pub fn new(function: &[f32]) -> Self {
+        let function: Vec<f32> = function.iter().map(|f| f.max(0.)).collect();
+        let count = function.len() as f32;
+        let mut cdf = vec![0.; function.len() + 1];
+        for i in 0..function.len() {
+            cdf[i + 1] = cdf[i] + function[i] / count;
+        }
+
+        let integral = cdf[function.len()];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0. {
+                *c / integral
+            } else {
+                i as f32 / count
+            };
+        }
+
+        Self {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.function.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    // Maps `u` in [0, 1) to a position in [0, 1). Returns the position, its
+    // density and the index of the segment it falls in.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let index = self.cdf.partition_point(|c| *c <= u).clamp(1, self.count()) - 1;
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0. {
+            (u - self.cdf[index]) / width
+        } else {
+            0.
+        };
+        let x = ((index as f32 + offset) / self.count() as f32).min(1. - f32::EPSILON);
+        (x, self.pdf(index), index)
+    }
+
+    // Chance that `sample` picks the segment `index`.
+    pub fn probability(&self, index: usize) -> f32 {
+        self.pdf(index) / self.count() as f32
+    }
+
+    // Density of the segment `index` with respect to a position in [0, 1).
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0. {
+            self.function[index] / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+// Samples a 2D function over [0, 1)², stored row by row, by picking a row
+// from the marginal distribution and then a column from that row.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(function: &[f32], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = function
+            .chunks(width)
+            .take(height)
+            .map(Distribution1D::new)
+            .collect();
+        let integrals: Vec<f32> = rows.iter().map(|row| row.integral()).collect();
+        Self {
+            rows,
+            marginal: Distribution1D::new(&integrals),
+        }
+    }
+
+    // Returns the sampled (column, row) position in [0, 1)² and its density.
+    pub fn sample(&self, u: f32, v: f32) -> ([f32; 2], f32) {
+        let (y, pdf_y, row) = self.marginal.sample(v);
+        let (x, pdf_x, _) = self.rows[row].sample(u);
+        ([x, y], pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let row = ((y * self.marginal.count() as f32) as usize).min(self.marginal.count() - 1);
+        let distribution = &self.rows[row];
+        let column = ((x * distribution.count() as f32) as usize).min(distribution.count() - 1);
+        if self.marginal.integral() > 0. {
+            distribution.function[column] / self.marginal.integral()
+        } else {
+            1.
+        }
+    }
+}
+
+#[cfg(test)]
+mod distribution_tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_1d() {
+        let distribution = Distribution1D::new(&[1., 3., 0., 0.]);
+        assert_eq!(distribution.integral(), 1.);
+
+        // The first quarter of the samples land in the first segment.
+        let (x, pdf, index) = distribution.sample(0.125);
+        assert_eq!(index, 0);
+        assert!((x - 0.125).abs() < 0.0001);
+        assert_eq!(pdf, 1.);
+
+        let (x, pdf, index) = distribution.sample(0.625);
+        assert_eq!(index, 1);
+        assert!((x - 0.375).abs() < 0.0001);
+        assert_eq!(pdf, 3.);
+
+        // Segments without weight are never picked.
+        let (_, _, index) = distribution.sample(0.9999);
+        assert_eq!(index, 1);
+
+        let uniform = Distribution1D::new(&[0., 0.]);
+        assert_eq!(uniform.sample(0.75).1, 1.);
+    }
+
+    #[test]
+    fn test_distribution_2d() {
+        let distribution = Distribution2D::new(&[0., 0., 1., 3.], 2, 2);
+        let ([x, y], pdf) = distribution.sample(0.5, 0.5);
+        assert!(y >= 0.5);
+        assert!(x >= 0.5);
+        assert!((pdf - distribution.pdf(x, y)).abs() < 0.0001);
+        assert_eq!(distribution.pdf(0.25, 0.25), 0.);
+    }
+}
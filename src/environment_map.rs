@@ -0,0 +1,177 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use image::Rgb32FImage;
+
+use super::distribution::Distribution2D;
+use super::miss_shaders::equirectangular_uv;
+use super::rand;
+use super::tone_mapping::luminance;
+use super::types::*;
+use super::vec::*;
+
+// A high dynamic range equirectangular map that lights the scene from all
+// directions. It uses the same mapping as `equirectangular_uv` and is
+// importance sampled by the luminance of its texels.
+pub struct EnvironmentMap {
+    image: Rgb32FImage,
+    distribution: Distribution2D,
+    intensity: f32,
+    // Rotation around +Y, as sine and cosine.
+    rotation: (f32, f32),
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Rgb32FImage) -> Self {
+        let (width, height) = image.dimensions();
+        // Rows near the poles cover less of the sphere.
+        let weights: Vec<f32> = image
+            .enumerate_pixels()
+            .map(|(_, y, pixel)| {
+                let elevation = ((y as f32 + 0.5) / height as f32 - 0.5) * PI;
+                luminance(&Color::from_values(pixel.0)) * elevation.cos()
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, width as usize, height as usize);
+        Self {
+            image,
+            distribution,
+            intensity: 1.,
+            rotation: (0., 1.),
+        }
+    }
+
+    // Reads a Radiance .hdr or OpenEXR file.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("Failed to load environment map {}: {}", path.display(), e))?;
+        Ok(Self::new(image.into_rgb32f()))
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    // Turns the map around +Y, positive angles move its center from -Z
+    // towards -X.
+    pub fn with_rotation(mut self, degrees: f32) -> Self {
+        self.rotation = degrees.to_radians().sin_cos();
+        self
+    }
+
+    fn rotate(&self, direction: &Direction, sign: f32) -> Direction {
+        let (sin, cos) = self.rotation;
+        let sin = sin * sign;
+        Direction::from_values([
+            direction.x() * cos + direction.z() * sin,
+            direction.y(),
+            direction.z() * cos - direction.x() * sin,
+        ])
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.image.width() as i64) as u32;
+        let y = y.clamp(0, self.image.height() as i64 - 1) as u32;
+        Color::from_values(self.image.get_pixel(x, y).0)
+    }
+
+    // Radiance arriving from `direction`, bilinearly filtered.
+    pub fn radiance(&self, direction: &Direction) -> Color {
+        let uv = equirectangular_uv(&self.rotate(direction, -1.));
+        let x = uv.x() * self.image.width() as f32 - 0.5;
+        let y = (1. - uv.y()) * self.image.height() as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0) * (1. - tx) + self.texel(x0 + 1, y0) * tx;
+        let bottom = self.texel(x0, y0 + 1) * (1. - tx) + self.texel(x0 + 1, y0 + 1) * tx;
+        (top * (1. - ty) + bottom * ty) * self.intensity
+    }
+
+    // Picks a direction with a probability proportional to the brightness of
+    // the map. Returns the direction, its radiance and its solid angle density.
+    pub fn sample(&self) -> (Direction, Color, f32) {
+        let ([u, y], pdf) = self.distribution.sample(rand::float(), rand::float());
+        let phi = (u - 0.5) * 2. * PI;
+        let elevation = (0.5 - y) * PI;
+        let cos_elevation = elevation.cos();
+        if pdf <= 0. || cos_elevation <= 0. {
+            return (Direction::new(), Color::new(), 0.);
+        }
+
+        let direction = Direction::from_values([
+            phi.sin() * cos_elevation,
+            elevation.sin(),
+            -phi.cos() * cos_elevation,
+        ]);
+        let direction = self.rotate(&direction, 1.);
+        let pdf = pdf / (2. * PI * PI * cos_elevation);
+        (direction, self.radiance(&direction), pdf)
+    }
+
+    // Solid angle density of `sample` returning `direction`.
+    pub fn pdf(&self, direction: &Direction) -> f32 {
+        let uv = equirectangular_uv(&self.rotate(direction, -1.));
+        let cos_elevation = ((uv.y() - 0.5) * PI).cos();
+        if cos_elevation <= 0. {
+            return 0.;
+        }
+
+        self.distribution.pdf(uv.x(), 1. - uv.y()) / (2. * PI * PI * cos_elevation)
+    }
+}
+
+#[cfg(test)]
+mod environment_map_tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_sample_bright_texel() {
+        // A dark map with one bright texel in the middle, which is -Z.
+        let mut image = Rgb32FImage::from_pixel(8, 4, Rgb([0.01, 0.01, 0.01]));
+        image.put_pixel(4, 1, Rgb([1000., 1000., 1000.]));
+        let map = EnvironmentMap::new(image).with_rotation(90.);
+
+        let mut bright = 0;
+        for _ in 0..100 {
+            let (direction, radiance, pdf) = map.sample();
+            assert!((length(&direction) - 1.).abs() < 0.001);
+            assert!((pdf - map.pdf(&direction)).abs() < 0.001 * pdf);
+            if radiance.x() > 1. {
+                bright += 1;
+                // Rotated by 90 degrees the texel ends up near -X.
+                assert!(direction.x() < 0.);
+            }
+        }
+        assert!(bright > 90);
+    }
+
+    #[test]
+    fn test_pdf_integrates_to_one() {
+        let mut image = Rgb32FImage::from_pixel(16, 8, Rgb([0.5, 0.5, 0.5]));
+        image.put_pixel(3, 2, Rgb([20., 10., 5.]));
+        let map = EnvironmentMap::new(image);
+
+        let steps = 256;
+        let mut integral = 0.;
+        for j in 0..steps {
+            let elevation = ((j as f32 + 0.5) / steps as f32 - 0.5) * PI;
+            for i in 0..steps {
+                let phi = (i as f32 + 0.5) / steps as f32 * 2. * PI;
+                let direction = Direction::from_values([
+                    phi.sin() * elevation.cos(),
+                    elevation.sin(),
+                    -phi.cos() * elevation.cos(),
+                ]);
+                let solid_angle = elevation.cos() * (PI / steps as f32) * (2. * PI / steps as f32);
+                integral += map.pdf(&direction) * solid_angle;
+            }
+        }
+        assert!((integral - 1.).abs() < 0.01);
+    }
+}
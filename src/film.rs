@@ -0,0 +1,189 @@
+use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+use super::tone_mapping::ToneMapper;
+use super::types::{Color, Normal};
+
+// Auxiliary data of the first visible surface. Depth is the distance along
+// the camera ray and infinite for pixels that see the background.
+#[derive(Clone, Copy)]
+pub struct Aovs {
+    pub albedo: Color,
+    pub normal: Normal,
+    pub depth: f32,
+}
+
+impl Default for Aovs {
+    fn default() -> Self {
+        Self {
+            albedo: Color::new(),
+            normal: Normal::new(),
+            depth: f32::INFINITY,
+        }
+    }
+}
+
+// Linear HDR framebuffer. Every pixel holds the sum of its samples together
+// with the number of samples taken, so films can be accumulated over passes.
+#[derive(Clone)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    sample_counts: Vec<u32>,
+    // Sums of the sample alphas, like the pixels.
+    alphas: Option<Vec<f32>>,
+    aovs: Option<Vec<Aovs>>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+        Self {
+            width,
+            height,
+            pixels: vec![Color::new(); size],
+            sample_counts: vec![0; size],
+            alphas: None,
+            aovs: None,
+        }
+    }
+
+    pub fn with_aovs(mut self) -> Self {
+        self.aovs = Some(vec![Aovs::default(); self.pixels.len()]);
+        self
+    }
+
+    pub fn with_alpha(mut self) -> Self {
+        self.alphas = Some(vec![0.; self.pixels.len()]);
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    // Adds `count` opaque samples whose average is `color`.
+    pub fn add_samples(&mut self, x: u32, y: u32, color: &Color, count: u32) {
+        self.add_samples_with_alpha(x, y, color, 1., count);
+    }
+
+    // Adds `count` samples whose averages are `color`, premultiplied by
+    // `alpha`, and `alpha`.
+    pub fn add_samples_with_alpha(
+        &mut self,
+        x: u32,
+        y: u32,
+        color: &Color,
+        alpha: f32,
+        count: u32,
+    ) {
+        let i = self.index(x, y);
+        self.pixels[i] += &(*color * count as f32);
+        self.sample_counts[i] += count;
+        if let Some(alphas) = self.alphas.as_mut() {
+            alphas[i] += alpha * count as f32;
+        }
+    }
+
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.sample_counts[self.index(x, y)]
+    }
+
+    // Average radiance of the pixel.
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        match self.sample_counts[i] {
+            0 => Color::new(),
+            n => self.pixels[i] / n as f32,
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.alphas.is_some()
+    }
+
+    // Average alpha of the pixel, films without alpha are opaque.
+    pub fn alpha(&self, x: u32, y: u32) -> f32 {
+        let i = self.index(x, y);
+        match (&self.alphas, self.sample_counts[i]) {
+            (Some(alphas), n) if n > 0 => alphas[i] / n as f32,
+            _ => 1.,
+        }
+    }
+
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    pub fn aovs(&self, x: u32, y: u32) -> Option<&Aovs> {
+        let i = self.index(x, y);
+        self.aovs.as_ref().map(|aovs| &aovs[i])
+    }
+
+    pub fn set_aovs(&mut self, x: u32, y: u32, aovs: &Aovs) {
+        let i = self.index(x, y);
+        if let Some(layers) = self.aovs.as_mut() {
+            layers[i] = *aovs;
+        }
+    }
+
+    pub fn to_rgb_image(&self, tone_mapper: &dyn ToneMapper) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            Rgb(tone_mapper.display(&self.pixel(x, y)))
+        })
+    }
+
+    // 8 bit images store straight alpha, so the color is divided by the
+    // alpha before tone mapping.
+    pub fn to_rgba_image(&self, tone_mapper: &dyn ToneMapper) -> RgbaImage {
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let alpha = self.alpha(x, y);
+            let color = if alpha > 0. {
+                self.pixel(x, y) / alpha
+            } else {
+                Color::new()
+            };
+            let [r, g, b] = tone_mapper.display(&color);
+            Rgba([r, g, b, (alpha.clamp(0., 1.) * 255. + 0.5) as u8])
+        })
+    }
+}
+
+#[cfg(test)]
+mod film_tests {
+    use super::Film;
+    use crate::tone_mapping::LinearToneMapper;
+    use crate::types::Color;
+
+    #[test]
+    fn test_accumulate() {
+        let mut film = Film::new(4, 2);
+        film.add_samples(3, 1, &Color::splat(1.0), 2);
+        film.add_samples(3, 1, &Color::splat(4.0), 1);
+        assert_eq!(film.sample_count(3, 1), 3);
+        assert_eq!(film.pixel(3, 1), Color::splat(2.0));
+        assert_eq!(film.pixel(0, 0), Color::new());
+        assert_eq!(film.alpha(3, 1), 1.);
+    }
+
+    #[test]
+    fn test_alpha() {
+        let mut film = Film::new(2, 1).with_alpha();
+        film.add_samples_with_alpha(0, 0, &Color::splat(0.5), 0.5, 2);
+        film.add_samples_with_alpha(0, 0, &Color::new(), 0., 2);
+        assert_eq!(film.alpha(0, 0), 0.25);
+
+        // Straight alpha in 8 bit images.
+        let image = film.to_rgba_image(&LinearToneMapper::new(0.));
+        assert_eq!(image.get_pixel(0, 0).0, [255, 255, 255, 64]);
+        assert_eq!(image.get_pixel(1, 0).0[3], 255);
+    }
+}
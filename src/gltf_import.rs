@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use gltf::camera::Projection;
+use gltf::image::Format;
+use gltf::khr_lights_punctual::Kind;
+use image::RgbaImage;
+use slotmap::DefaultKey;
+
+use super::acceleration_structure::BuildQuality;
+use super::hittable::TriangleMesh;
+use super::light::{DirectionalLight, Lights, PointLight};
+use super::materials::PBRMaterial;
+use super::resources::Resources;
+use super::scene::Instance;
+use super::scene_description::CameraDescription;
+use super::texture::{ImageTexture, SolidColorTexture};
+use super::types::*;
+use super::vec::*;
+
+// Imports the default scene of a .gltf or .glb file. Meshes, materials and
+// images are added to `resources`, every mesh primitive of every node becomes
+// an instance and punctual lights are appended to `lights`. The cameras found
+// in the node hierarchy are returned in document order.
+pub fn import_gltf(
+    path: &Path,
+    resources: &mut Resources,
+    instances: &mut Vec<Instance>,
+    lights: &mut Lights,
+    build_quality: BuildQuality,
+) -> Result<Vec<CameraDescription>, String> {
+    let (document, buffers, images) =
+        gltf::import(path).map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+
+    let mut importer = Importer {
+        resources,
+        buffers: &buffers,
+        images: images.iter().map(Some).collect(),
+        image_keys: HashMap::new(),
+        materials: HashMap::new(),
+        meshes: HashMap::new(),
+        instances,
+        lights,
+        cameras: Vec::new(),
+        build_quality,
+    };
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| format!("{} contains no scenes", path.display()))?;
+    for node in scene.nodes() {
+        importer.visit(&node, &Transform::new())?;
+    }
+
+    Ok(importer.cameras)
+}
+
+struct Importer<'a> {
+    resources: &'a mut Resources,
+    buffers: &'a [gltf::buffer::Data],
+    images: Vec<Option<&'a gltf::image::Data>>,
+    image_keys: HashMap<usize, DefaultKey>,
+    materials: HashMap<Option<usize>, DefaultKey>,
+    meshes: HashMap<(usize, usize), DefaultKey>,
+    instances: &'a mut Vec<Instance>,
+    lights: &'a mut Lights,
+    cameras: Vec<CameraDescription>,
+    build_quality: BuildQuality,
+}
+
+impl<'a> Importer<'a> {
+    fn visit(&mut self, node: &gltf::Node, parent: &Transform) -> Result<(), String> {
+        let transform = *parent * to_transform(&node.transform().matrix());
+        let origin = Position::from_values([
+            transform.colums[0][3],
+            transform.colums[1][3],
+            transform.colums[2][3],
+        ]);
+        // Cameras and lights look down their local -Z axis.
+        let backward = normalize(&Direction::from_values([
+            transform.colums[0][2],
+            transform.colums[1][2],
+            transform.colums[2][2],
+        ]));
+
+        // Zero scale hides a node, it has nothing left to intersect.
+        let invertible = transform.inverse().is_some();
+        if let Some(mesh) = node.mesh().filter(|_| invertible) {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+
+                if let Some(geometry) = self.mesh(&mesh, &primitive)? {
+                    let material = self.material(&primitive.material())?;
+                    // Closed meshes are entered by refracted rays, so back faces
+                    // are never culled.
+                    let instance =
+                        Instance::new(geometry, self.instances.len() as u32, material, false)
+                            .with_transform(&transform);
+                    self.instances.push(instance);
+                }
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            if let Projection::Perspective(perspective) = camera.projection() {
+                self.cameras.push(CameraDescription {
+                    origin: origin.data,
+                    look_at: (origin - backward).data,
+                    fov: perspective.yfov().to_degrees(),
+                    aperture: 0.,
+                    focus_distance: None,
+                });
+            }
+        }
+
+        if let Some(light) = node.light() {
+            let color = Color::from_values(light.color());
+            match light.kind() {
+                Kind::Directional => self.lights.add(
+                    DirectionalLight::new(backward)
+                        .with_color(color)
+                        .with_intensity(light.intensity()),
+                ),
+                // Spot lights are imported without their cone.
+                Kind::Point | Kind::Spot { .. } => self.lights.add(
+                    PointLight::new(origin)
+                        .with_color(color)
+                        .with_intensity(light.intensity()),
+                ),
+            }
+        }
+
+        for child in node.children() {
+            self.visit(&child, &transform)?;
+        }
+
+        Ok(())
+    }
+
+    fn mesh(
+        &mut self,
+        mesh: &gltf::Mesh,
+        primitive: &gltf::Primitive,
+    ) -> Result<Option<DefaultKey>, String> {
+        let id = (mesh.index(), primitive.index());
+        if let Some(key) = self.meshes.get(&id) {
+            return Ok(Some(*key));
+        }
+
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let positions: Vec<Position> = match reader.read_positions() {
+            Some(positions) => positions.map(Position::from_values).collect(),
+            None => return Ok(None),
+        };
+        let normals: Vec<Normal> = reader
+            .read_normals()
+            .map(|normals| normals.map(Normal::from_values).collect())
+            .unwrap_or_default();
+        // glTF puts the texture origin in the top left corner.
+        let tex_coords: Vec<TextureCoordinate> = reader
+            .read_tex_coords(0)
+            .map(|tex_coords| {
+                tex_coords
+                    .into_f32()
+                    .map(|[u, v]| TextureCoordinate::from_values([u, 1. - v]))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        if indices.len() < 3 {
+            return Ok(None);
+        }
+
+        let key = self
+            .resources
+            .add_hittable(TriangleMesh::new_with_build_quality(
+                positions,
+                normals,
+                tex_coords,
+                indices,
+                self.build_quality,
+            ));
+        self.meshes.insert(id, key);
+        Ok(Some(key))
+    }
+
+    fn material(&mut self, material: &gltf::Material) -> Result<DefaultKey, String> {
+        if let Some(key) = self.materials.get(&material.index()) {
+            return Ok(*key);
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let albedo = self.texture(
+            pbr.base_color_texture(),
+            &Color::from_values([r, g, b]),
+            None,
+            true,
+        )?;
+        let roughness = self.texture(
+            pbr.metallic_roughness_texture(),
+            &Color::splat(pbr.roughness_factor()),
+            Some(1),
+            false,
+        )?;
+        let metal = self.texture(
+            pbr.metallic_roughness_texture(),
+            &Color::splat(pbr.metallic_factor()),
+            Some(2),
+            false,
+        )?;
+        let emission = self.texture(
+            material.emissive_texture(),
+            &(Color::from_values(material.emissive_factor())
+                * material.emissive_strength().unwrap_or(1.)),
+            None,
+            true,
+        )?;
+        let transmission = material
+            .transmission()
+            .map(|t| t.transmission_factor())
+            .unwrap_or(0.);
+        let ior = material.ior().unwrap_or(1.5);
+
+        let key = self.resources.add_material(PBRMaterial::new(
+            albedo,
+            roughness,
+            metal,
+            emission,
+            ior,
+            transmission,
+            0.5,
+        ));
+        self.materials.insert(material.index(), key);
+        Ok(key)
+    }
+
+    // A solid color when there is no texture, otherwise the image scaled by
+    // `factor`.
+    fn texture(
+        &mut self,
+        info: Option<gltf::texture::Info>,
+        factor: &Color,
+        channel: Option<usize>,
+        srgb: bool,
+    ) -> Result<DefaultKey, String> {
+        let info = match info {
+            Some(info) => info,
+            None => return Ok(self.resources.add_texture(SolidColorTexture::new(factor))),
+        };
+
+        let mut texture =
+            ImageTexture::new(self.image(info.texture().source().index())?).with_factor(factor);
+        if let Some(channel) = channel {
+            texture = texture.with_channel(channel);
+        }
+        if srgb {
+            texture = texture.with_srgb();
+        }
+
+        Ok(self.resources.add_texture(texture))
+    }
+
+    fn image(&mut self, index: usize) -> Result<DefaultKey, String> {
+        if let Some(key) = self.image_keys.get(&index) {
+            return Ok(*key);
+        }
+
+        let data = self.images[index]
+            .take()
+            .ok_or_else(|| format!("Missing image {}", index))?;
+        let key = self.resources.add_image(to_rgba_image(data)?);
+        self.image_keys.insert(index, key);
+        Ok(key)
+    }
+}
+
+// glTF matrices are column major 4x4, transforms are stored as three rows.
+fn to_transform(matrix: &[[f32; 4]; 4]) -> Transform {
+    let mut transform = Transform::new();
+    for (row, values) in transform.colums.iter_mut().enumerate() {
+        *values = Vec4::from_values([
+            matrix[0][row],
+            matrix[1][row],
+            matrix[2][row],
+            matrix[3][row],
+        ]);
+    }
+    transform
+}
+
+fn to_rgba_image(data: &gltf::image::Data) -> Result<RgbaImage, String> {
+    let (channels, bytes) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+
+    let component = |texel: &[u8], c: usize| -> u8 {
+        let bytes = &texel[c * bytes..(c + 1) * bytes];
+        match bytes.len() {
+            1 => bytes[0],
+            2 => (u16::from_ne_bytes([bytes[0], bytes[1]]) >> 8) as u8,
+            _ => {
+                let value = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (value.clamp(0., 1.) * 255. + 0.5) as u8
+            }
+        }
+    };
+
+    let pixels = data
+        .pixels
+        .chunks_exact(channels * bytes)
+        .flat_map(|texel| match channels {
+            1 => {
+                let r = component(texel, 0);
+                [r, r, r, 255]
+            }
+            2 => {
+                let r = component(texel, 0);
+                [r, component(texel, 1), 0, 255]
+            }
+            3 => [
+                component(texel, 0),
+                component(texel, 1),
+                component(texel, 2),
+                255,
+            ],
+            _ => [
+                component(texel, 0),
+                component(texel, 1),
+                component(texel, 2),
+                component(texel, 3),
+            ],
+        })
+        .collect();
+
+    RgbaImage::from_raw(data.width, data.height, pixels)
+        .ok_or_else(|| "Invalid image data".to_string())
+}
+
+#[cfg(test)]
+mod gltf_import_tests {
+    use super::import_gltf;
+    use crate::acceleration_structure::BuildQuality;
+    use crate::light::Lights;
+    use crate::resources::Resources;
+
+    // One triangle referenced by a child node and by a hidden zero scale node,
+    // a camera and a directional light.
+    const GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "extensionsUsed": ["KHR_lights_punctual"],
+        "extensions": { "KHR_lights_punctual": { "lights": [{ "type": "directional", "intensity": 3 }] } },
+        "scene": 0,
+        "scenes": [{ "nodes": [0, 2, 3] }],
+        "nodes": [
+            { "translation": [1, 2, 3], "children": [1], "extensions": { "KHR_lights_punctual": { "light": 0 } } },
+            { "mesh": 0, "scale": [2, 2, 2] },
+            { "camera": 0, "translation": [0, 0, 10] },
+            { "mesh": 0, "scale": [0, 0, 0] }
+        ],
+        "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.5, "znear": 0.1 } }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+        "buffers": [{
+            "byteLength": 44,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA="
+        }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ]
+    }"#;
+
+    #[test]
+    fn test_import_node_hierarchy() {
+        let path = std::env::temp_dir().join("toy_tracer_test_import_node_hierarchy.gltf");
+        std::fs::write(&path, GLTF).unwrap();
+
+        let mut resources = Resources::default();
+        let mut instances = Vec::new();
+        let mut lights = Lights::new();
+        let cameras = import_gltf(
+            &path,
+            &mut resources,
+            &mut instances,
+            &mut lights,
+            BuildQuality::default(),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(instances.len(), 1);
+        let transform = &instances[0].transform;
+        assert_eq!(transform.colums[0][0], 2.);
+        assert_eq!(transform.colums[1][3], 2.);
+        assert_eq!(transform.colums[2][3], 3.);
+
+        assert_eq!(lights.data().len(), 1);
+        assert_eq!(cameras.len(), 1);
+        assert_eq!(cameras[0].origin, [0., 0., 10.]);
+        assert_eq!(cameras[0].look_at, [0., 0., 9.]);
+    }
+}
@@ -0,0 +1,187 @@
+use super::hittable::Hittable;
+use super::intersection::Intersection;
+use super::material::{HitRecord, Material};
+use super::math_utils::power_heuristic;
+use super::rand;
+use super::ray::Ray;
+use super::raytracer::{ClosestHitShader, ShaderContext};
+use super::scene::Instance;
+use super::shader_binding_table::Payload;
+use super::types::*;
+use super::vec::*;
+
+// Shades a hit with its material, samples the scene lights, the environment
+// and the emissive instances, and continues the path in the direction the
+// material samples. Light found both ways is combined with multiple importance
+// sampling, using the power heuristic.
+pub struct PathTracingHitShader {}
+
+impl ClosestHitShader for PathTracingHitShader {
+    fn hit(
+        &self,
+        context: &ShaderContext,
+        instance_id: u32,
+        intersection: &Intersection,
+        payload: &mut Payload,
+    ) {
+        let resources = context.resources;
+        let instance = context.scene.instance(instance_id as usize);
+        let geometry = resources.hittable(instance.geometry_index);
+        let material_id = geometry
+            .material(intersection)
+            .unwrap_or(instance.material_id);
+        let material = resources.material(material_id);
+
+        let hit_record = HitRecord::new(instance, geometry, intersection);
+        let wo = hit_record.wo();
+        let bounce = material.sample(resources, &hit_record, &wo);
+
+        // The previous bounce could also have sampled this emitter directly.
+        let mut direct_light = material.emit(resources, &hit_record);
+        if payload.pdf > 0. && context.lights.is_emitter(instance_id) {
+            let light_pdf = emitter_pdf(context, instance, geometry, &hit_record);
+            direct_light *= power_heuristic(payload.pdf, light_pdf);
+        }
+
+        let position = hit_record.position();
+        for light in context.lights.data() {
+            let ray_dir = light.sample(&position);
+            let (f, lobe) = material.eval(resources, &hit_record, &wo, &ray_dir);
+            if lobe.is_empty() {
+                continue;
+            }
+
+            let shadow_ray = Ray::new(&position, &ray_dir);
+            let distance = light.distance(&position);
+            if !context
+                .ray_tracer
+                .occluded(&shadow_ray, context.scene, resources, 0.001, distance)
+            {
+                let cos_theta = dot(&ray_dir, &hit_record.normal).abs();
+                direct_light = direct_light + light.radiance(&position) * f * cos_theta;
+            }
+        }
+
+        // Delta materials only see the environment and the emitters through
+        // the rays they sample. The others are lit whether or not their own
+        // sample succeeded, a failed sample only ends the path.
+        if !material.is_delta() {
+            direct_light = direct_light + sample_environment(context, material, &hit_record, &wo);
+            direct_light = direct_light + sample_emitter(context, material, &hit_record, &wo);
+        }
+
+        payload.radiance = payload.radiance + payload.throughput * direct_light;
+        payload.throughput *= bounce.color;
+        payload.pdf = bounce.pdf;
+        payload.next_ray =
+            (bounce.pdf > 0. || material.is_delta()).then(|| Ray::new(&position, &bounce.wi));
+    }
+}
+
+// Solid angle density of `sample_emitter` picking the point of `hit_record`.
+fn emitter_pdf(
+    context: &ShaderContext,
+    instance: &Instance,
+    geometry: &dyn Hittable,
+    hit_record: &HitRecord,
+) -> f32 {
+    let intersection = &hit_record.intersection;
+    let direction = intersection.ray.direction();
+    let distance = intersection.t * length(direction);
+    let cos_light = dot(&normalize(direction), &hit_record.normal).abs();
+    if cos_light <= 0. {
+        return 0.;
+    }
+
+    let area_pdf = geometry.surface_pdf(instance, intersection);
+    area_pdf * distance * distance / (cos_light * context.lights.emitters().len() as f32)
+}
+
+// Light from the environment map, weighted against the chance that the
+// material samples the same direction.
+fn sample_environment(
+    context: &ShaderContext,
+    material: &dyn Material,
+    hit_record: &HitRecord,
+    wo: &Direction,
+) -> Color {
+    let Some(environment) = context.lights.environment() else {
+        return Color::new();
+    };
+
+    let resources = context.resources;
+    let (wi, radiance, light_pdf) = environment.sample();
+    let (f, lobe) = material.eval(resources, hit_record, wo, &wi);
+    let shadow_ray = Ray::new(&hit_record.position(), &wi);
+    if light_pdf <= 0.
+        || lobe.is_empty()
+        || context.ray_tracer.occluded(
+            &shadow_ray,
+            context.scene,
+            context.resources,
+            0.001,
+            f32::INFINITY,
+        )
+    {
+        return Color::new();
+    }
+
+    let (material_pdf, _) = material.pdf(resources, hit_record, wo, &wi);
+    let cos_theta = dot(&wi, &hit_record.normal).abs();
+    let weight = power_heuristic(light_pdf, material_pdf);
+    f * radiance * (cos_theta * weight / light_pdf)
+}
+
+// Light from a point on one of the emissive instances, picked with equal
+// chance, weighted against the chance that the material samples it.
+fn sample_emitter(
+    context: &ShaderContext,
+    material: &dyn Material,
+    hit_record: &HitRecord,
+    wo: &Direction,
+) -> Color {
+    let emitters = context.lights.emitters();
+    if emitters.is_empty() {
+        return Color::new();
+    }
+
+    let resources = context.resources;
+    let index = ((rand::float() * emitters.len() as f32) as usize).min(emitters.len() - 1);
+    let instance = context.scene.instance(emitters[index] as usize);
+    let geometry = resources.hittable(instance.geometry_index);
+    let position = hit_record.position();
+    let Some((intersection, area_pdf)) = geometry.sample_surface(instance, &position) else {
+        return Color::new();
+    };
+
+    let light_record = HitRecord::new(instance, geometry, &intersection);
+    let wi = *intersection.ray.direction();
+    let (f, lobe) = material.eval(resources, hit_record, wo, &wi);
+    let cos_light = dot(&wi, &light_record.normal).abs();
+    if area_pdf <= 0. || lobe.is_empty() || cos_light <= 0. {
+        return Color::new();
+    }
+
+    // Stop short of the emitter so its own surface doesn't occlude the point.
+    let shadow_ray = Ray::new(&position, &wi);
+    let distance = intersection.t * (1. - 1e-3);
+    if context
+        .ray_tracer
+        .occluded(&shadow_ray, context.scene, resources, 0.001, distance)
+    {
+        return Color::new();
+    }
+
+    let emitter_material = geometry
+        .material(&intersection)
+        .unwrap_or(instance.material_id);
+    let radiance = resources
+        .material(emitter_material)
+        .emit(resources, &light_record);
+    let light_pdf =
+        area_pdf * intersection.t * intersection.t / (cos_light * emitters.len() as f32);
+    let (material_pdf, _) = material.pdf(resources, hit_record, wo, &wi);
+    let cos_theta = dot(&wi, &hit_record.normal).abs();
+    let weight = power_heuristic(light_pdf, material_pdf);
+    f * radiance * (cos_theta * weight / light_pdf)
+}
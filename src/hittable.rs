@@ -1,25 +1,93 @@
-use super::acceleration_structure::BottomLevelAccelerationStructure;
+use super::acceleration_structure::{BottomLevelAccelerationStructure, BuildQuality};
 use super::bounding_box::*;
+use super::distribution::Distribution1D;
 use super::intersection::*;
+use super::rand;
 use super::ray::*;
+use super::scene::Instance;
 use super::types::*;
 use super::vec::*;
+use slotmap::DefaultKey;
+use std::f32::consts::PI;
 use std::time::Instant;
 
 pub trait Hittable {
     fn uid(&self) -> usize;
-    fn intersect(
+    // Intersects a ray given in object space.
+    fn intersect(&self, ray: &Ray, cull: bool, t_min: f32, t_max: f32) -> Option<Intersection>;
+
+    // Whether anything is hit between `t_min` and `t_max`, the ray is given in
+    // object space. Geometry that can stop at the first hit overrides this.
+    fn occluded(&self, ray: &Ray, cull: bool, t_min: f32, t_max: f32) -> bool {
+        self.intersect(ray, cull, t_min, t_max).is_some()
+    }
+
+    // Like `intersect`, but hits that `accept` rejects are ignored. Geometry
+    // with more than one primitive overrides this to keep looking behind a
+    // rejected hit.
+    fn intersect_with(
         &self,
-        object_to_world: &Transform,
         ray: &Ray,
         cull: bool,
         t_min: f32,
         t_max: f32,
-    ) -> Option<Intersection>;
+        accept: &mut dyn FnMut(&Intersection) -> bool,
+    ) -> Option<Intersection> {
+        self.intersect(ray, cull, t_min, t_max)
+            .filter(|intersection| accept(intersection))
+    }
 
-    fn normal(&self, object_to_world: &Transform, intersection: &Intersection) -> Normal;
+    // The world space normal at the hit.
+    fn normal(&self, instance: &Instance, intersection: &Intersection) -> Normal;
     fn uv(&self, object_to_world: &Transform, intersection: &Intersection) -> TextureCoordinate;
     fn bounding_box(&self) -> Option<BoundingBox>;
+
+    // The material of the hit primitive, when it overrides the material of the instance.
+    fn material(&self, _intersection: &Intersection) -> Option<DefaultKey> {
+        None
+    }
+
+    // The interpolated vertex color, for geometry that has one.
+    fn vertex_color(&self, _intersection: &Intersection) -> Option<Color> {
+        None
+    }
+
+    // The per primitive materials, empty when the geometry has none.
+    fn face_materials(&self) -> &[Option<DefaultKey>] {
+        &[]
+    }
+
+    // Picks a point on the surface of `instance`, uniformly by world space
+    // area for geometry that isn't scaled non-uniformly. Returns the hit a
+    // ray from `origin` to the point makes and the density of the point with
+    // respect to world space area. Geometry that returns None can't be used
+    // as a light.
+    fn sample_surface(
+        &self,
+        _instance: &Instance,
+        _origin: &Position,
+    ) -> Option<(Intersection, f32)> {
+        None
+    }
+
+    // The area density of `sample_surface` picking the hit point.
+    fn surface_pdf(&self, _instance: &Instance, _intersection: &Intersection) -> f32 {
+        0.
+    }
+}
+
+// The hit of the ray from `origin` through `point`, with a normalized
+// direction so `t` is the distance.
+fn intersection_towards(
+    origin: &Position,
+    point: &Position,
+    primitive_id: u32,
+    barycentrics: &Barycentrics,
+) -> Intersection {
+    let direction = *point - origin;
+    let distance = length(&direction);
+    let ray = Ray::new(origin, &(direction / distance));
+    Intersection::new(&ray, distance, primitive_id, barycentrics)
 }
 
 pub struct Sphere {
@@ -34,22 +102,23 @@ impl Sphere {
             position: *position,
         }
     }
+
+    // Points are picked uniformly on the sphere in object space, the
+    // transform stretches the area around them by the determinant times the
+    // length of the transformed normal.
+    fn area_pdf(&self, instance: &Instance, normal: &Normal) -> f32 {
+        let stretch = instance.transform.determinant().abs()
+            * length(&instance.normal_matrix.transform_vector(normal));
+        1. / (4. * PI * self.radius * self.radius * stretch)
+    }
 }
 
 impl Hittable for Sphere {
-    fn intersect(
-        &self,
-        object_to_world: &Transform,
-        ray: &Ray,
-        cull: bool,
-        t_min: f32,
-        t_max: f32,
-    ) -> Option<Intersection> {
-        let r = object_to_world.colums[0][0] * self.radius;
-        let oc = ray.origin - (*object_to_world * Vec4::from(self.position));
+    fn intersect(&self, ray: &Ray, _cull: bool, t_min: f32, t_max: f32) -> Option<Intersection> {
+        let oc = ray.origin - self.position;
         let a = dot(&ray.dir, &ray.dir);
         let half_b = dot(&oc, &ray.dir);
-        let r2 = r * r;
+        let r2 = self.radius * self.radius;
         let c = dot(&oc, &oc) - r2;
 
         let discr = half_b * half_b - a * c;
@@ -70,10 +139,11 @@ impl Hittable for Sphere {
         return Some(Intersection::new(ray, root, 0, &Barycentrics::new()));
     }
 
-    fn normal(&self, object_to_world: &Transform, intersection: &Intersection) -> Normal {
-        let n =
-            intersection.ray.at(intersection.t) - (*object_to_world * Vec4::from(self.position));
-        normalize(&n)
+    fn normal(&self, instance: &Instance, intersection: &Intersection) -> Normal {
+        let position = instance
+            .world_to_object
+            .transform_point(&intersection.ray.at(intersection.t));
+        instance.normal_to_world(&(position - self.position))
     }
 
     fn uv(&self, _: &Transform, _intersection: &Intersection) -> TextureCoordinate {
@@ -85,6 +155,29 @@ impl Hittable for Sphere {
         Some(BoundingBox::new(self.position - r, self.position + r))
     }
 
+    fn sample_surface(
+        &self,
+        instance: &Instance,
+        origin: &Position,
+    ) -> Option<(Intersection, f32)> {
+        let z = 1. - 2. * rand::float();
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * rand::float();
+        let normal = Normal::from_values([r * phi.cos(), r * phi.sin(), z]);
+        let point = instance
+            .transform
+            .transform_point(&(self.position + normal * self.radius));
+        let intersection = intersection_towards(origin, &point, 0, &Barycentrics::new());
+        Some((intersection, self.area_pdf(instance, &normal)))
+    }
+
+    fn surface_pdf(&self, instance: &Instance, intersection: &Intersection) -> f32 {
+        let position = instance
+            .world_to_object
+            .transform_point(&intersection.ray.at(intersection.t));
+        self.area_pdf(instance, &normalize(&(position - self.position)))
+    }
+
     fn uid(&self) -> usize {
         1
     }
@@ -95,17 +188,27 @@ pub struct TriangleMesh {
     normals: Vec<Normal>,
     tex_coords: Vec<TextureCoordinate>,
     indices: Vec<u32>,
+    // One entry per triangle, empty when the mesh has no materials of its own.
+    materials: Vec<Option<DefaultKey>>,
+    // One entry per vertex, empty when the mesh has no vertex colors.
+    colors: Vec<Color>,
+    // Picks triangles by their object space area when the mesh is a light.
+    areas: Distribution1D,
     acceleration_structure: BottomLevelAccelerationStructure,
 }
 
 impl Hittable for TriangleMesh {
-    fn intersect(
+    fn intersect(&self, ray: &Ray, cull: bool, t_min: f32, t_max: f32) -> Option<Intersection> {
+        self.intersect_with(ray, cull, t_min, t_max, &mut |_| true)
+    }
+
+    fn intersect_with(
         &self,
-        object_to_world: &Transform,
         ray: &Ray,
         cull: bool,
         t_min: f32,
         t_max: f32,
+        accept: &mut dyn FnMut(&Intersection) -> bool,
     ) -> Option<Intersection> {
         let mut intersection = None;
 
@@ -115,81 +218,60 @@ impl Hittable for TriangleMesh {
             let mut closest = t_max;
             for i in 0..self.indices.len() / 3 {
                 let index = i * 3;
-                let i0 = self.indices[index] as usize;
-                let i1 = self.indices[index + 1] as usize;
-                let i2 = self.indices[index + 2] as usize;
-
-                let v0 = *object_to_world * &Vec4::from(self.positions[i0]);
-                let v1 = *object_to_world * &Vec4::from(self.positions[i1]);
-                let v2 = *object_to_world * &Vec4::from(self.positions[i2]);
-
+                let vertices = self.triangle(index);
                 if let Some((t, u, v)) =
-                    self.ray_triangle_intersect(ray, cull, t_min, t_max, &v0, &v1, &v2)
+                    self.ray_triangle_intersect(ray, cull, t_min, t_max, &vertices)
                 {
-                    if t < closest {
-                        intersection = Some(Intersection::new(
-                            ray,
-                            t,
-                            index as u32,
-                            &Barycentrics::from_values([u, v]),
-                        ));
-
+                    let hit =
+                        Intersection::new(ray, t, index as u32, &Barycentrics::from_values([u, v]));
+                    if t < closest && accept(&hit) {
+                        intersection = Some(hit);
                         closest = t;
                     }
                 }
             }
         } else {
-            let result = self
-                .acceleration_structure
-                .hit_test(object_to_world, ray, t_min, t_max);
-
-            for index in result {
-                let i = index as usize * 3;
-                let i0 = self.indices[i as usize] as usize;
-                let i1 = self.indices[i + 1] as usize;
-                let i2 = self.indices[i + 2] as usize;
-
-                let v0 = *object_to_world * &Vec4::from(self.positions[i0]);
-                let v1 = *object_to_world * &Vec4::from(self.positions[i1]);
-                let v2 = *object_to_world * &Vec4::from(self.positions[i2]);
-
-                let mut closest = t_max;
-                if let Some((t, u, v)) =
-                    self.ray_triangle_intersect(ray, cull, t_min, t_max, &v0, &v1, &v2)
-                {
-                    if t < closest {
-                        intersection = Some(Intersection::new(
-                            ray,
-                            t,
-                            i as u32,
-                            &Barycentrics::from_values([u, v]),
-                        ));
-
-                        closest = t;
+            self.acceleration_structure
+                .closest_hit(ray, t_min, t_max, |triangle, t_max| {
+                    let index = triangle as usize * 3;
+                    let vertices = self.triangle(index);
+                    let (t, u, v) =
+                        self.ray_triangle_intersect(ray, cull, t_min, t_max, &vertices)?;
+                    let hit =
+                        Intersection::new(ray, t, index as u32, &Barycentrics::from_values([u, v]));
+                    if !accept(&hit) {
+                        return None;
                     }
-                }
-            }
+
+                    intersection = Some(hit);
+                    Some(t)
+                });
         }
         let duration = start.elapsed();
         //println!("Time elapsed in object trace is: {:?}", duration);
         intersection
     }
 
-    fn normal(&self, object_to_world: &Transform, intersection: &Intersection) -> Normal {
+    fn occluded(&self, ray: &Ray, cull: bool, t_min: f32, t_max: f32) -> bool {
+        self.acceleration_structure
+            .any_hit(ray, t_min, t_max, |triangle, t_max| {
+                let vertices = self.triangle(triangle as usize * 3);
+                let (t, _, _) = self.ray_triangle_intersect(ray, cull, t_min, t_max, &vertices)?;
+                Some(t)
+            })
+    }
+
+    fn normal(&self, instance: &Instance, intersection: &Intersection) -> Normal {
         let i = intersection.primitive_id as usize;
         let i0 = self.indices[i] as usize;
         let i1 = self.indices[1 + i] as usize;
         let i2 = self.indices[2 + i] as usize;
 
-        let tr = object_to_world.transposed();
-
-        let n1 = tr
-            * self.normals[i0]
-            * (1. - intersection.barycentrics.x() - intersection.barycentrics.y());
-        let n2 = tr * self.normals[i1] * intersection.barycentrics.x();
-        let n3 = tr * self.normals[i2] * intersection.barycentrics.y();
-        let n = n1 + n2 + n3;
-        normalize(&Vec3::from(n))
+        let n1 =
+            self.normals[i0] * (1. - intersection.barycentrics.x() - intersection.barycentrics.y());
+        let n2 = self.normals[i1] * intersection.barycentrics.x();
+        let n3 = self.normals[i2] * intersection.barycentrics.y();
+        instance.normal_to_world(&(n1 + n2 + n3))
     }
     fn uv(&self, _: &Transform, intersection: &Intersection) -> TextureCoordinate {
         let i = intersection.primitive_id as usize;
@@ -205,20 +287,91 @@ impl Hittable for TriangleMesh {
     }
 
     fn bounding_box(&self) -> Option<BoundingBox> {
-        Some(self.acceleration_structure.bounding_box())
+        self.acceleration_structure.bounding_box()
     }
 
     fn uid(&self) -> usize {
         2
     }
+
+    fn material(&self, intersection: &Intersection) -> Option<DefaultKey> {
+        let triangle = intersection.primitive_id as usize / 3;
+        self.materials.get(triangle).copied().flatten()
+    }
+
+    fn face_materials(&self) -> &[Option<DefaultKey>] {
+        &self.materials
+    }
+
+    fn sample_surface(
+        &self,
+        instance: &Instance,
+        origin: &Position,
+    ) -> Option<(Intersection, f32)> {
+        if self.areas.integral() <= 0. {
+            return None;
+        }
+
+        let (_, _, triangle) = self.areas.sample(rand::float());
+        let su = rand::float().sqrt();
+        let barycentrics = Barycentrics::from_values([rand::float() * su, 1. - su]);
+        let [v0, v1, v2] = self.world_triangle(instance, triangle);
+        let point = v0 * (1. - barycentrics.x() - barycentrics.y())
+            + v1 * barycentrics.x()
+            + v2 * barycentrics.y();
+
+        let intersection = intersection_towards(origin, &point, triangle as u32 * 3, &barycentrics);
+        let pdf = self.surface_pdf(instance, &intersection);
+        Some((intersection, pdf))
+    }
+
+    fn surface_pdf(&self, instance: &Instance, intersection: &Intersection) -> f32 {
+        let triangle = intersection.primitive_id as usize / 3;
+        let [v0, v1, v2] = self.world_triangle(instance, triangle);
+        let area = 0.5 * length(&cross(&(v1 - v0), &(v2 - v0)));
+        if area > 0. {
+            self.areas.probability(triangle) / area
+        } else {
+            0.
+        }
+    }
+
+    fn vertex_color(&self, intersection: &Intersection) -> Option<Color> {
+        if self.colors.is_empty() {
+            return None;
+        }
+
+        let i = intersection.primitive_id as usize;
+        let c1 = self.colors[self.indices[i] as usize]
+            * (1. - intersection.barycentrics.x() - intersection.barycentrics.y());
+        let c2 = self.colors[self.indices[i + 1] as usize] * intersection.barycentrics.x();
+        let c3 = self.colors[self.indices[i + 2] as usize] * intersection.barycentrics.y();
+        Some(c1 + c2 + c3)
+    }
 }
 
 impl TriangleMesh {
     pub fn new(
+        positions: Vec<Position>,
+        normals: Vec<Normal>,
+        tex_coords: Vec<TextureCoordinate>,
+        indices: Vec<u32>,
+    ) -> Self {
+        Self::new_with_build_quality(
+            positions,
+            normals,
+            tex_coords,
+            indices,
+            BuildQuality::default(),
+        )
+    }
+
+    pub fn new_with_build_quality(
         positions: Vec<Position>,
         mut normals: Vec<Normal>,
         mut tex_coords: Vec<TextureCoordinate>,
         indices: Vec<u32>,
+        build_quality: BuildQuality,
     ) -> Self {
         if normals.len() == 0 {
             normals.resize(positions.len(), Normal::new());
@@ -252,38 +405,73 @@ impl TriangleMesh {
         }
 
         let acceleration_structure =
-            BottomLevelAccelerationStructure::new(&positions, Some(&indices));
+            BottomLevelAccelerationStructure::new(&positions, &indices, build_quality);
+        let areas: Vec<f32> = indices
+            .chunks(3)
+            .map(|triangle| {
+                let v0 = &positions[triangle[0] as usize];
+                let v1 = &positions[triangle[1] as usize];
+                let v2 = &positions[triangle[2] as usize];
+                0.5 * length(&cross(&(*v1 - v0), &(*v2 - v0)))
+            })
+            .collect();
 
         Self {
             positions,
             normals,
             tex_coords,
             indices,
+            materials: Vec::new(),
+            colors: Vec::new(),
+            areas: Distribution1D::new(&areas),
             acceleration_structure,
         }
     }
 
+    fn world_triangle(&self, instance: &Instance, triangle: usize) -> [Position; 3] {
+        [0, 1, 2].map(|corner| {
+            instance
+                .transform
+                .transform_point(&self.positions[self.indices[triangle * 3 + corner] as usize])
+        })
+    }
+
+    pub fn with_materials(mut self, materials: Vec<Option<DefaultKey>>) -> Self {
+        self.materials = materials;
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    // The object space corners of the triangle whose indices start at `index`.
+    fn triangle(&self, index: usize) -> [Position; 3] {
+        [0, 1, 2].map(|corner| self.positions[self.indices[index + corner] as usize])
+    }
+
     fn ray_triangle_intersect(
         &self,
         ray: &Ray,
         cull: bool,
-        _t_min: f32,
-        _t_max: f32,
-        v0: &Position,
-        v1: &Position,
-        v2: &Position,
+        t_min: f32,
+        t_max: f32,
+        [v0, v1, v2]: &[Position; 3],
     ) -> Option<(f32, f32, f32)> {
         let v0v1 = *v1 - v0;
         let v0v2 = *v2 - v0;
         let pvec = cross(ray.direction(), &v0v2);
         let det = dot(&v0v1, &pvec);
 
+        // No absolute epsilon here, the determinant scales with the triangle
+        // size and with the length of the object space ray direction.
         if cull {
-            if det < 0.000001 {
+            if det <= 0. {
                 return None;
             }
         } else {
-            if det.abs() < 0.000001 {
+            if det == 0. {
                 return None;
             }
         }
@@ -291,7 +479,7 @@ impl TriangleMesh {
         let inv_det = 1. / det;
         let tvec = *ray.origin() - v0;
         let u = dot(&pvec, &tvec) * inv_det;
-        if u < 0. || u > 1. {
+        if !(0. ..=1.).contains(&u) {
             return None;
         }
 
@@ -302,6 +490,10 @@ impl TriangleMesh {
         }
 
         let t = dot(&v0v2, &qvec) * inv_det;
+        if t < t_min || t >= t_max {
+            return None;
+        }
+
         Some((t, u, v))
     }
 }
@@ -315,19 +507,12 @@ pub struct XYRect {
 }
 
 impl Hittable for XYRect {
-    fn intersect(
-        &self,
-        object_to_world: &Transform,
-        ray: &Ray,
-        cull: bool,
-        t_min: f32,
-        t_max: f32,
-    ) -> Option<Intersection> {
+    fn intersect(&self, _ray: &Ray, _cull: bool, _t_min: f32, _t_max: f32) -> Option<Intersection> {
         todo!()
     }
 
-    fn normal(&self, object_to_world: &Transform, intersection: &Intersection) -> Normal {
-        *object_to_world * &Vec4::from(Normal::from_values([0.0, 0.0, -1.0]))
+    fn normal(&self, instance: &Instance, _intersection: &Intersection) -> Normal {
+        instance.normal_to_world(&Normal::from_values([0.0, 0.0, -1.0]))
     }
 
     fn uv(&self, object_to_world: &Transform, intersection: &Intersection) -> TextureCoordinate {
@@ -1,6 +1,6 @@
 use super::ray::*;
 use super::types::*;
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 pub struct Intersection {
     pub ray: Ray,
     pub t: f32,
@@ -17,4 +17,9 @@ impl Intersection {
             barycentrics: *barycentrics,
         }
     }
+
+    // The same hit along another ray, for moving between object and world space.
+    pub fn with_ray(&self, ray: &Ray) -> Self {
+        Self { ray: *ray, ..*self }
+    }
 }
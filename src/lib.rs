@@ -0,0 +1,81 @@
+pub mod acceleration_structure;
+pub mod bounding_box;
+pub mod brdf;
+pub mod camera;
+pub mod cpu_tracer;
+pub mod default_camera;
+pub mod default_ray_generation_shader;
+pub mod disney_brdf_evaluate;
+pub mod disney_brdf_pdf;
+pub mod disney_brdf_sample;
+pub mod distribution;
+pub mod environment_map;
+pub mod film;
+pub mod gltf_import;
+pub mod hit_shaders;
+pub mod hittable;
+pub mod intersection;
+pub mod light;
+pub mod mat;
+pub mod material;
+pub mod materials;
+pub mod math_utils;
+pub mod miss_shaders;
+pub mod normal_ray_generation_shader;
+pub mod obj_import;
+pub mod onb;
+pub mod output;
+pub mod ply_import;
+pub mod quaternion;
+pub mod rand;
+pub mod ray;
+pub mod raytracer;
+pub mod resources;
+pub mod scene;
+pub mod scene_description;
+pub mod shader_binding_table;
+pub mod texture;
+pub mod tone_mapping;
+pub mod types;
+pub mod vec;
+pub mod vec_add;
+pub mod vec_div;
+pub mod vec_mul;
+pub mod vec_sub;
+
+pub use acceleration_structure::{
+    BottomLevelAccelerationStructure, BuildQuality, TopLevelAccelerationStructure,
+};
+pub use cpu_tracer::CPUTracer;
+pub use default_camera::DefaultCamera;
+pub use default_ray_generation_shader::RayGenerator;
+pub use environment_map::EnvironmentMap;
+pub use film::{Aovs, Film};
+pub use gltf_import::import_gltf;
+pub use hit_shaders::PathTracingHitShader;
+pub use hittable::{Hittable, Sphere, TriangleMesh};
+pub use light::{DirectionalLight, Light, Lights, PointLight};
+pub use material::{Bounce, HitRecord, Lobe, Material};
+pub use materials::{
+    ConductorMaterial, DiffuseMaterial, DisneyMaterial, Metal, MirrorMaterial, PBRMaterial,
+    TranslucentMaterial,
+};
+pub use miss_shaders::{EnvironmentMapMissShader, GradientMissShader};
+pub use normal_ray_generation_shader::NormalRayGenerator;
+pub use obj_import::import_obj;
+pub use output::{write_film, OutputFormat};
+pub use ply_import::load_ply;
+pub use quaternion::Quaternion;
+pub use raytracer::{
+    AnyHitShader, ClosestHitShader, MissShader, RayGenerationShader, RayTracer, ShaderContext,
+    TraceSettings,
+};
+pub use resources::Resources;
+pub use scene::Instance;
+pub use scene_description::{LoadedScene, RenderSettings, SceneDescription};
+pub use shader_binding_table::{HitGroup, Payload, ShaderBindingTable};
+pub use texture::{CheckerTexture, ImageTexture, SolidColorTexture, Texture};
+pub use tone_mapping::{
+    AcesToneMapper, ExtendedReinhardToneMapper, HableToneMapper, LinearToneMapper,
+    LuminanceReinhardToneMapper, ReinhardToneMapper, ToneMapper,
+};
@@ -1,12 +1,25 @@
 use std::ops::Deref;
+use std::sync::Arc;
 
-use crate::{types::Color, vec::normalize};
+use crate::{
+    types::Color,
+    vec::{dot, length, normalize},
+};
 
+use super::environment_map::EnvironmentMap;
+use super::resources::Resources;
+use super::scene::Instance;
 use super::types::{Direction, Position};
 
 pub trait Light {
     fn sample(&self, position: &Position) -> Direction;
-    fn color(&self) -> Color;
+    // Light arriving at `position` from the sampled direction.
+    fn radiance(&self, position: &Position) -> Color;
+
+    // Distance from `position` to the light along the sampled direction.
+    fn distance(&self, _position: &Position) -> f32 {
+        f32::INFINITY
+    }
 }
 
 pub struct DirectionalLight {
@@ -23,6 +36,16 @@ impl DirectionalLight {
             intenstity: 10.0,
         }
     }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intenstity = intensity;
+        self
+    }
 }
 
 impl Light for DirectionalLight {
@@ -30,23 +53,70 @@ impl Light for DirectionalLight {
         self.position
     }
 
-    fn color(&self) -> Color {
+    fn radiance(&self, _: &Position) -> Color {
         self.color * self.intenstity
     }
 }
 
+pub struct PointLight {
+    position: Position,
+    intensity: f32,
+    color: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Position) -> Self {
+        Self {
+            position,
+            color: Color::from_values([1., 1., 1.]),
+            intensity: 10.0,
+        }
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, position: &Position) -> Direction {
+        normalize(&(self.position - position))
+    }
+
+    fn distance(&self, position: &Position) -> f32 {
+        length(&(self.position - position))
+    }
+
+    // Falls off with the square of the distance.
+    fn radiance(&self, position: &Position) -> Color {
+        let offset = self.position - position;
+        self.color * (self.intensity / dot(&offset, &offset))
+    }
+}
+
 pub struct SphericalLight {
     radius: f32,
     intensity: f32,
 }
 
+#[derive(Default)]
 pub struct Lights {
     data: Vec<Box<dyn Light>>,
+    // Sampled by the hit shaders, the miss shader that draws it shares it.
+    environment: Option<Arc<EnvironmentMap>>,
+    // Indices of the instances with emissive materials, in increasing order.
+    emitters: Vec<u32>,
 }
 
 impl Lights {
     pub fn new() -> Self {
-        Self { data: Vec::new() }
+        Self::default()
     }
 
     pub fn add<T>(&mut self, light: T)
@@ -59,7 +129,75 @@ impl Lights {
     pub fn data(&self) -> &Vec<Box<dyn Light>> {
         &self.data
     }
+
+    pub fn set_environment(&mut self, environment: Arc<EnvironmentMap>) {
+        self.environment = Some(environment);
+    }
+
+    pub fn environment(&self) -> Option<&EnvironmentMap> {
+        self.environment.as_deref()
+    }
+
+    // Makes the instance at `index` of the scene an area light.
+    pub fn add_emitter(&mut self, index: u32) {
+        if let Err(position) = self.emitters.binary_search(&index) {
+            self.emitters.insert(position, index);
+        }
+    }
+
+    // Adds every instance that has an emissive material, either its own or
+    // one of its primitives', and geometry that can be sampled.
+    pub fn add_emitters(&mut self, resources: &Resources, instances: &[Instance]) {
+        let emissive = |material| resources.material(material).is_emissive(resources);
+        for (index, instance) in instances.iter().enumerate() {
+            let geometry = resources.hittable(instance.geometry_index);
+            let faces = geometry.face_materials();
+            let emits = if faces.is_empty() {
+                emissive(instance.material_id)
+            } else {
+                faces
+                    .iter()
+                    .any(|material| emissive(material.unwrap_or(instance.material_id)))
+            };
+            if emits
+                && geometry
+                    .sample_surface(instance, &Position::new())
+                    .is_some()
+            {
+                self.add_emitter(index as u32);
+            }
+        }
+    }
+
+    pub fn emitters(&self) -> &[u32] {
+        &self.emitters
+    }
+
+    pub fn is_emitter(&self, index: u32) -> bool {
+        self.emitters.binary_search(&index).is_ok()
+    }
 }
 
 unsafe impl Send for Lights {}
 unsafe impl Sync for Lights {}
+
+#[cfg(test)]
+mod light_tests {
+    use super::*;
+    use crate::vec::XAccessor;
+
+    #[test]
+    fn test_point_light_falloff() {
+        let light = PointLight::new(Position::new()).with_intensity(8.);
+        let near = light.radiance(&Position::from_values([0., 2., 0.]));
+        let far = light.radiance(&Position::from_values([0., 4., 0.]));
+        assert!((near.x() - 2.).abs() < 0.0001);
+        assert!((far.x() - near.x() / 4.).abs() < 0.0001);
+
+        let light = DirectionalLight::new(Direction::from_values([0., 1., 0.]));
+        assert_eq!(
+            light.radiance(&Position::new()).x(),
+            light.radiance(&Position::from_values([0., 100., 0.])).x()
+        );
+    }
+}
@@ -1,179 +1,185 @@
-pub mod acceleration_structure;
-pub mod bounding_box;
-pub mod brdf;
-pub mod camera;
-pub mod cpu_tracer;
-pub mod default_camera;
-pub mod default_ray_generation_shader;
-pub mod disney_brdf_evaluate;
-pub mod disney_brdf_pdf;
-pub mod disney_brdf_sample;
-pub mod hittable;
-pub mod intersection;
-pub mod light;
-pub mod mat;
-pub mod material;
-pub mod materials;
-pub mod math_utils;
-pub mod onb;
-pub mod rand;
-pub mod ray;
-pub mod raytracer;
-pub mod resources;
-pub mod scene;
-pub mod texture;
-pub mod types;
-pub mod vec;
-pub mod vec_add;
-pub mod vec_div;
-pub mod vec_mul;
-pub mod vec_sub;
-
-use acceleration_structure::*;
-use cpu_tracer::*;
-use default_camera::DefaultCamera;
-use default_ray_generation_shader::RayGenerator;
-use hittable::*;
-use light::{DirectionalLight, Lights};
-use materials::*;
-use raytracer::*;
-use resources::Resources;
-use scene::*;
-use texture::*;
-use types::*;
-use vec::*;
-
-fn degrees_to_radians(degrees: f32) -> f32 {
-    degrees * std::f32::consts::PI / 180.
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use toy_tracer::{
+    write_film, AcesToneMapper, BuildQuality, CPUTracer, ExtendedReinhardToneMapper,
+    HableToneMapper, LinearToneMapper, LuminanceReinhardToneMapper, NormalRayGenerator,
+    OutputFormat, RayGenerator, RayTracer, ReinhardToneMapper, SceneDescription,
+    ShaderBindingTable, ToneMapper, TopLevelAccelerationStructure, TraceSettings,
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Integrator {
+    /// Unidirectional path tracer
+    Path,
+    /// World space normals of the first hit
+    Normals,
 }
 
-fn main() {
-    let obj_file = "./assets/stanford-bunny.obj";
-    let (models, _) =
-        tobj::load_obj(&obj_file, &tobj::LoadOptions::default()).expect("Failed to load file");
-
-    let mut positions = Vec::new();
-    let mut normals = Vec::new();
-    let mut tex_coords = Vec::new();
-    let mut indices: Vec<u32> = Vec::new();
-
-    for (_, m) in models.iter().enumerate() {
-        let mesh = &m.mesh;
-
-        indices.extend(mesh.indices.iter());
+#[derive(Clone, Copy, ValueEnum)]
+enum Bvh {
+    /// Linear BVH, quick to build
+    Fast,
+    /// Binned SAH, slower to build but faster to render
+    High,
+}
 
-        for v in (0..mesh.positions.len()).step_by(3) {
-            positions.push(Position::from_values([
-                mesh.positions[v],
-                mesh.positions[v + 1],
-                mesh.positions[v + 2],
-            ]))
-        }
+#[derive(Clone, Copy, ValueEnum)]
+enum ToneMapping {
+    /// Clamp to [0, 1]
+    Linear,
+    /// Per channel Reinhard
+    Reinhard,
+    /// Reinhard on luminance
+    ReinhardLuminance,
+    /// Luminance Reinhard that maps the white point to white
+    ReinhardExtended,
+    /// ACES filmic
+    Aces,
+    /// Uncharted 2 filmic curve by John Hable
+    Hable,
+}
 
-        for n in (0..mesh.normals.len()).step_by(3) {
-            normals.push(Normal::from_values([
-                mesh.normals[n],
-                mesh.normals[n + 1],
-                mesh.normals[n + 2],
-            ]))
-        }
+/// Renders a scene description to an image.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Scene description file
+    #[arg(default_value = "./assets/scenes/bunny.json")]
+    scene: PathBuf,
+    /// Output image, the format follows from the extension. EXR and PFM store
+    /// linear radiance, other formats are tone mapped
+    #[arg(short, long, default_value = "output.png")]
+    output: PathBuf,
+    /// Add albedo, normal and depth layers to EXR output
+    #[arg(long)]
+    layers: bool,
+    /// Image width, overrides the scene's render settings
+    #[arg(long)]
+    width: Option<u32>,
+    /// Image height, overrides the scene's render settings
+    #[arg(long)]
+    height: Option<u32>,
+    /// Samples per pixel, overrides the scene's render settings
+    #[arg(long)]
+    spp: Option<u32>,
+    /// Maximum path depth, overrides the scene's render settings
+    #[arg(long)]
+    max_depth: Option<u32>,
+    /// Mesh BVH build quality, overrides the scene's render settings
+    #[arg(long, value_enum)]
+    bvh: Option<Bvh>,
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+    /// Seed for reproducible renders
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long, value_enum, default_value_t = Integrator::Path)]
+    integrator: Integrator,
+    /// Display transform for 8 bit output
+    #[arg(long, value_enum, default_value_t = ToneMapping::Reinhard)]
+    tone_mapping: ToneMapping,
+    /// Exposure in stops, applied before tone mapping
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: f32,
+    /// Smallest radiance that maps to white for extended Reinhard
+    #[arg(long, default_value_t = 4.0)]
+    white_point: f32,
+}
 
-        for t in (0..mesh.texcoords.len()).step_by(2) {
-            tex_coords.push(TextureCoordinate::from_values([
-                mesh.texcoords[t],
-                mesh.texcoords[t + 1],
-            ]))
+impl Args {
+    fn tone_mapper(&self) -> Box<dyn ToneMapper> {
+        let exposure = self.exposure;
+        match self.tone_mapping {
+            ToneMapping::Linear => Box::new(LinearToneMapper::new(exposure)),
+            ToneMapping::Reinhard => Box::new(ReinhardToneMapper::new(exposure)),
+            ToneMapping::ReinhardLuminance => Box::new(LuminanceReinhardToneMapper::new(exposure)),
+            ToneMapping::ReinhardExtended => {
+                Box::new(ExtendedReinhardToneMapper::new(exposure, self.white_point))
+            }
+            ToneMapping::Aces => Box::new(AcesToneMapper::new(exposure)),
+            ToneMapping::Hable => Box::new(HableToneMapper::new(exposure)),
         }
     }
+}
 
-    let mut resources = Resources::default();
-    let sphere = resources.add_hittable(Sphere::new(1.0, &Position::default()));
-    let teapot = resources.add_hittable(TriangleMesh::new(positions, normals, tex_coords, indices));
-
-    let grey_texture = resources.add_texture(SolidColorTexture::new(&Color::from_values([
-        0.05, 0.3, 0.25,
-    ])));
-    let white_texture = resources.add_texture(SolidColorTexture::new(&Color::splat(1.0)));
-    let black_texture = resources.add_texture(SolidColorTexture::new(&Color::new()));
-    let checker_texture =
-        resources.add_texture(CheckerTexture::new(white_texture, black_texture, 3.0));
-
-    let purple_texture =
-        resources.add_texture(SolidColorTexture::new(&Color::from_values([0.4, 0.1, 0.4])));
-
-    let pbr = resources.add_material(PBRMaterial::new(
-        checker_texture,
-        grey_texture,
-        black_texture,
-        black_texture,
-        1.0,
-        0.0,
-        0.5,
-    ));
-
-    let pbr2 = resources.add_material(PBRMaterial::new(
-        purple_texture,
-        grey_texture,
-        black_texture,
-        black_texture,
-        1.1,
-        1.0,
-        0.5,
-    ));
-
-    let pbr3 = resources.add_material(PBRMaterial::new(
-        white_texture,
-        white_texture,
-        black_texture,
-        black_texture,
-        1.5,
-        0.0,
-        0.5,
-    ));
-
-    let mut instances = Vec::new();
-    // Floor
-    instances.push(
-        Instance::new(sphere, 0, pbr, true)
-            .with_position(0., -100., 0.)
-            .with_scale(100., 100., 100.),
-    );
-
-    instances.push(
-        Instance::new(teapot, 1, pbr2, false)
-            .with_position(0.0, -2., 0.)
-            .with_uniform_scale(50.),
-    );
+fn run(args: Args) -> Result<(), String> {
+    let format = OutputFormat::from_path(&args.output)?;
+    if args.layers && !format.supports_aovs() {
+        return Err("Extra layers are only supported for EXR output".to_string());
+    }
 
-    instances.push(
-        Instance::new(teapot, 2, pbr, true)
-            .with_position(5.0, -2., 4.)
-            .with_uniform_scale(50.),
-    );
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|e| format!("Failed to create thread pool: {}", e))?;
+    }
 
-    instances.push(
-        Instance::new(teapot, 3, pbr3, true)
-            .with_position(-5.0, -2., -3.)
-            .with_uniform_scale(50.),
-    );
+    let mut description = SceneDescription::from_file(&args.scene)?;
+    let settings = &mut description.render;
+    settings.width = args.width.unwrap_or(settings.width);
+    settings.height = args.height.unwrap_or(settings.height);
+    settings.spp = args.spp.unwrap_or(settings.spp);
+    settings.max_depth = args.max_depth.unwrap_or(settings.max_depth);
+    settings.bvh = match args.bvh {
+        Some(Bvh::Fast) => BuildQuality::Fast,
+        Some(Bvh::High) => BuildQuality::High,
+        None => settings.bvh,
+    };
+    if settings.width == 0 || settings.height == 0 {
+        return Err("Image width and height must be at least 1 pixel".to_string());
+    }
 
-    let ac = TopLevelAccelerationStructure::new(&resources.hittables(), &instances);
-    let width = 1920;
-    let height = 1080;
-    let origin = &Position::from_values([3., 4., 15.]);
-    let look_at = &Direction::from_values([0., 3., 0.]);
-    let camera = DefaultCamera::new(
-        &origin,
-        &look_at,
-        width as f32 / height as f32,
-        45.,
-        0.15,
-        distance(&look_at, &origin),
+    let base_dir = args.scene.parent().unwrap_or_else(|| Path::new(""));
+    let scene = description.build(base_dir)?;
+    let settings = &scene.settings;
+
+    let ac = TopLevelAccelerationStructure::new(scene.resources.hittables(), &scene.instances);
+    let tracer = match args.integrator {
+        Integrator::Path => CPUTracer::new(RayGenerator {
+            camera: scene.camera,
+        }),
+        Integrator::Normals => CPUTracer::new(NormalRayGenerator {
+            camera: scene.camera,
+        }),
+    };
+    let tracer =
+        tracer.with_shader_binding_table(ShaderBindingTable::with_background(scene.background))?;
+    let tracer = if scene.transparent {
+        tracer.with_alpha()
+    } else {
+        tracer
+    };
+    let tracer = match args.seed {
+        Some(seed) => tracer.with_seed(seed),
+        None => tracer,
+    };
+    let tracer = if args.layers {
+        tracer.with_aovs()
+    } else {
+        tracer
+    };
+
+    let film = tracer.trace(
+        &TraceSettings {
+            width: settings.width,
+            height: settings.height,
+            spp: settings.spp,
+            max_depth: settings.max_depth,
+        },
+        &ac,
+        &scene.lights,
+        &scene.resources,
     );
 
-    let mut lights = Lights::new();
-    // lights.add(DirectionalLight::new(Position::from_values([-1., 1., 1.])));
+    write_film(&film, &args.output, args.tone_mapper().as_ref())
+}
 
-    let tracer = CPUTracer::new(RayGenerator { camera });
-    tracer.trace(1024, 32, width, height, &ac, &lights, &resources);
+fn main() {
+    if let Err(error) = run(Args::parse()) {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
 }
@@ -13,8 +13,8 @@ impl<const COLUMS: usize, const ROWS: usize> Matrix<COLUMS, ROWS> {
 
     pub fn identity() -> Self {
         let mut colums = [Vector::<ROWS>::new(); COLUMS];
-        for i in 0..COLUMS.min(ROWS) {
-            colums[i][i] = 1.;
+        for (i, colum) in colums.iter_mut().enumerate().take(ROWS) {
+            colum[i] = 1.;
         }
 
         Self { colums }
@@ -31,6 +31,159 @@ impl<const COLUMS: usize, const ROWS: usize> Matrix<COLUMS, ROWS> {
     }
 }
 
+// Affine transforms are stored as three rows, the fourth column holds the
+// translation.
+impl Matrix<3, 4> {
+    pub fn from_translation(translation: &Vec3) -> Self {
+        let mut result = Self::new();
+        for row in 0..3 {
+            result.colums[row][3] = translation[row];
+        }
+        result
+    }
+
+    pub fn from_scale(scale: &Vec3) -> Self {
+        let mut result = Self::new();
+        for row in 0..3 {
+            result.colums[row][row] = scale[row];
+        }
+        result
+    }
+
+    // Counter clockwise rotation by `angle` radians about `axis`.
+    pub fn from_rotation(axis: &Direction, angle: f32) -> Self {
+        let [x, y, z] = normalize(axis).data;
+        let (sin, cos) = angle.sin_cos();
+        let t = 1. - cos;
+        Self::from_linear([
+            [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y],
+            [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x],
+            [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos],
+        ])
+    }
+
+    // Rotates about X, then Y, then Z. Angles are in radians.
+    pub fn from_euler_angles(x: f32, y: f32, z: f32) -> Self {
+        Self::from_rotation(&Direction::from_values([0., 0., 1.]), z)
+            * Self::from_rotation(&Direction::from_values([0., 1., 0.]), y)
+            * Self::from_rotation(&Direction::from_values([1., 0., 0.]), x)
+    }
+
+    // Places an object at `eye` with its local -Z axis pointing at `target`
+    // and its local Y axis as close to `up` as possible. When looking along
+    // `up` the world axis least aligned with the view stands in for it.
+    pub fn look_at(eye: &Position, target: &Position, up: &Direction) -> Self {
+        let backward = normalize(&(*eye - target));
+        let up = if dot(&normalize(up), &backward).abs() > 0.999 {
+            let axis = (0..3)
+                .min_by(|a, b| backward[*a].abs().total_cmp(&backward[*b].abs()))
+                .unwrap();
+            let mut fallback = Direction::new();
+            fallback[axis] = 1.;
+            fallback
+        } else {
+            *up
+        };
+        let right = normalize(&cross(&up, &backward));
+        let up = cross(&backward, &right);
+        let mut result = Self::from_linear([
+            [right.x(), up.x(), backward.x()],
+            [right.y(), up.y(), backward.y()],
+            [right.z(), up.z(), backward.z()],
+        ]);
+        for row in 0..3 {
+            result.colums[row][3] = eye[row];
+        }
+        result
+    }
+
+    fn from_linear(rows: [[f32; 3]; 3]) -> Self {
+        let mut result = Self::new();
+        for (row, values) in result.colums.iter_mut().zip(rows) {
+            *row = Vec4::from_values([values[0], values[1], values[2], 0.]);
+        }
+        result
+    }
+
+    pub fn transform_point(&self, point: &Position) -> Position {
+        *self * Vec4::from_values([point.x(), point.y(), point.z(), 1.])
+    }
+
+    pub fn transform_vector(&self, vector: &Vec3) -> Vec3 {
+        *self * Vec4::from_values([vector.x(), vector.y(), vector.z(), 0.])
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        Vec3::from_values([self.colums[0][3], self.colums[1][3], self.colums[2][3]])
+    }
+
+    pub fn set_translation(&mut self, translation: &Vec3) {
+        for row in 0..3 {
+            self.colums[row][3] = translation[row];
+        }
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let m = &self.colums;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // The inverse of the affine transform, a singular transform has no
+    // inverse and returns None. Only an exactly singular matrix is rejected,
+    // tiny but valid scales still invert.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if !det.is_normal() {
+            return None;
+        }
+
+        let m = &self.colums;
+        let inv_det = 1. / det;
+        let mut result = Self::from_linear([
+            [
+                (m[1][1] * m[2][2] - m[1][2] * m[2][1]) * inv_det,
+                (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det,
+                (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det,
+            ],
+            [
+                (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv_det,
+                (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det,
+                (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv_det,
+            ],
+            [
+                (m[1][0] * m[2][1] - m[1][1] * m[2][0]) * inv_det,
+                (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv_det,
+                (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv_det,
+            ],
+        ]);
+
+        let translation = self.translation();
+        let inverse_translation = -(result * Vec4::from(translation));
+        result.set_translation(&inverse_translation);
+        Some(result)
+    }
+}
+
+// Composes two affine transforms, the implicit last row is [0, 0, 0, 1].
+impl std::ops::Mul<Matrix<3, 4>> for Matrix<3, 4> {
+    type Output = Matrix<3, 4>;
+
+    fn mul(self, rhs: Matrix<3, 4>) -> Self::Output {
+        let mut result = Matrix::<3, 4>::new();
+        for (row, out) in self.colums.iter().zip(result.colums.iter_mut()) {
+            // The translation column picks up the implicit 1 of the last row.
+            let column = |c: usize| {
+                let w = if c == 3 { 1. } else { 0. };
+                Vec4::from_values([rhs.colums[0][c], rhs.colums[1][c], rhs.colums[2][c], w])
+            };
+            *out = Vec4::from_values([0, 1, 2, 3].map(|c| dot(row, &column(c))));
+        }
+        result
+    }
+}
+
 impl std::ops::Mul<Vector<4>> for Matrix<3, 4> {
     type Output = Vector<3>;
 
@@ -47,9 +200,9 @@ impl std::ops::Mul<&Vector<4>> for Matrix<3, 4> {
     type Output = Vector<3>;
 
     fn mul(self, rhs: &Vector<4>) -> Self::Output {
-        let x = dot(&self.colums[0], &rhs);
-        let y = dot(&self.colums[1], &rhs);
-        let z = dot(&self.colums[2], &rhs);
+        let x = dot(&self.colums[0], rhs);
+        let y = dot(&self.colums[1], rhs);
+        let z = dot(&self.colums[2], rhs);
 
         Vec3::from_values([x, y, z])
     }
@@ -67,3 +220,67 @@ impl std::ops::Mul<Vector<3>> for Matrix<4, 3> {
         Vec4::from_values([x, y, z, w])
     }
 }
+
+#[cfg(test)]
+mod mat_tests {
+    use super::*;
+
+    fn assert_near(a: &Transform, b: &Transform) {
+        for row in 0..3 {
+            for column in 0..4 {
+                assert!((a.colums[row][column] - b.colums[row][column]).abs() < 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rotation() {
+        let rotation = Transform::from_rotation(
+            &Direction::from_values([0., 0., 1.]),
+            std::f32::consts::FRAC_PI_2,
+        );
+        let x = rotation * Vec4::from_values([1., 0., 0., 0.]);
+        assert!((x.y() - 1.).abs() < 0.0001);
+        assert_near(
+            &rotation,
+            &Transform::from_euler_angles(0., 0., std::f32::consts::FRAC_PI_2),
+        );
+    }
+
+    #[test]
+    fn test_inverse() {
+        let transform = Transform::from_translation(&Vec3::from_values([1., 2., 3.]))
+            * Transform::from_euler_angles(0.3, 0.2, 0.1)
+            * Transform::from_scale(&Vec3::from_values([2., 3., 4.]));
+        assert_near(
+            &(transform * transform.inverse().unwrap()),
+            &Transform::new(),
+        );
+        assert!(Transform::from_scale(&Vec3::new()).inverse().is_none());
+
+        let small = Transform::from_scale(&Vec3::from_values([0.001, 0.001, 0.001]));
+        assert_near(&(small * small.inverse().unwrap()), &Transform::new());
+    }
+
+    #[test]
+    fn test_look_at() {
+        let transform = Transform::look_at(
+            &Position::from_values([0., 0., 5.]),
+            &Position::new(),
+            &Direction::from_values([0., 1., 0.]),
+        );
+        let forward = transform * Vec4::from_values([0., 0., -1., 0.]);
+        assert!((forward.z() + 1.).abs() < 0.0001);
+        assert_eq!(transform.translation().z(), 5.);
+
+        // Looking straight up along the up vector still gives a rotation.
+        let transform = Transform::look_at(
+            &Position::new(),
+            &Position::from_values([0., 5., 0.]),
+            &Direction::from_values([0., 1., 0.]),
+        );
+        let forward = transform * Vec4::from_values([0., 0., -1., 0.]);
+        assert!((forward.y() - 1.).abs() < 0.0001);
+        assert!((transform.determinant() - 1.).abs() < 0.0001);
+    }
+}
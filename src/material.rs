@@ -1,11 +1,60 @@
+use std::f32::consts::PI;
+use std::ops::BitOr;
+
+use super::hittable::Hittable;
 use super::intersection::*;
+use super::math_utils::same_hemisphere;
 use super::resources::Resources;
+use super::scene::Instance;
 use super::types::*;
+use super::vec::{dot, normalize};
+
+// The kinds of scattering a sample or an evaluation of a BSDF involves,
+// combined with `|`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Lobe(u8);
+
+impl Lobe {
+    pub const NONE: Lobe = Lobe(0);
+    // A delta distribution, like a perfect mirror. It can only be sampled,
+    // evaluating it for a given pair of directions gives zero.
+    pub const SPECULAR: Lobe = Lobe(1);
+    pub const GLOSSY: Lobe = Lobe(2);
+    pub const DIFFUSE: Lobe = Lobe(4);
+    pub const REFLECTION: Lobe = Lobe(8);
+    pub const TRANSMISSION: Lobe = Lobe(16);
+
+    pub fn contains(self, lobe: Lobe) -> bool {
+        self.0 & lobe.0 == lobe.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self == Self::NONE
+    }
+
+    pub fn is_specular(self) -> bool {
+        self.contains(Self::SPECULAR)
+    }
+}
+
+impl BitOr for Lobe {
+    type Output = Lobe;
+
+    fn bitor(self, rhs: Lobe) -> Lobe {
+        Lobe(self.0 | rhs.0)
+    }
+}
 
 #[derive(Default)]
 pub struct Bounce {
     pub wi: Direction,
+    // The BSDF times the cosine over the density of `wi`.
     pub color: Color,
+    // Solid angle density of `wi`, zero when it is not known or the lobe is a
+    // delta distribution.
+    pub pdf: f32,
+    // The lobe `wi` was sampled from.
+    pub lobe: Lobe,
 }
 
 impl Bounce {
@@ -13,8 +62,20 @@ impl Bounce {
         Self {
             wi: *wi,
             color: *color,
+            pdf: 0.,
+            lobe: Lobe::NONE,
         }
     }
+
+    pub fn with_pdf(mut self, pdf: f32) -> Self {
+        self.pdf = pdf;
+        self
+    }
+
+    pub fn with_lobe(mut self, lobe: Lobe) -> Self {
+        self.lobe = lobe;
+        self
+    }
 }
 
 #[derive(Default)]
@@ -24,11 +85,26 @@ pub struct HitRecord {
     pub uv: TextureCoordinate,
     pub front_facing: bool,
     pub instance_id: u32,
+    // Multiplies the albedo of the material.
+    pub vertex_color: Option<Color>,
     pub bounce: Bounce,
     pub direct_light: Color,
 }
 
 impl HitRecord {
+    pub fn new(instance: &Instance, geometry: &dyn Hittable, intersection: &Intersection) -> Self {
+        let normal = geometry.normal(instance, intersection);
+        Self {
+            intersection: *intersection,
+            normal,
+            uv: geometry.uv(&instance.transform, intersection),
+            front_facing: dot(&normal, intersection.ray.direction()) < 0.0,
+            instance_id: instance.instance_id,
+            vertex_color: geometry.vertex_color(intersection),
+            ..Default::default()
+        }
+    }
+
     pub fn position(&self) -> Position {
         self.intersection.ray.at(self.intersection.t)
     }
@@ -40,14 +116,86 @@ impl HitRecord {
     pub fn ray_direction(&self) -> &Direction {
         self.intersection.ray.direction()
     }
+
+    // The direction light leaves in towards the origin of the hit ray.
+    pub fn wo(&self) -> Direction {
+        normalize(&-self.ray_direction())
+    }
+
+    // The normal flipped to the side of `wo`, for two sided surfaces.
+    pub fn forward_normal(&self, wo: &Direction) -> Direction {
+        if dot(wo, &self.normal) < 0.0 {
+            -self.normal
+        } else {
+            self.normal
+        }
+    }
+
+    pub fn tint(&self, color: Color) -> Color {
+        match self.vertex_color {
+            Some(vertex_color) => color * vertex_color,
+            None => color,
+        }
+    }
 }
 
 pub trait Material {
     fn uid(&self) -> usize;
 
-    fn evaluate(&self, _: &Resources, _hit_record: &HitRecord) -> Bounce;
+    // Picks a direction `wi` for light to arrive from, given that it leaves
+    // along `wo`. Directions point away from the surface.
+    fn sample(&self, _: &Resources, _hit_record: &HitRecord, _wo: &Direction) -> Bounce;
 
     fn emit(&self, _: &Resources, _hit_record: &HitRecord) -> Color {
         Color::new()
     }
+
+    fn albedo(&self, _: &Resources, _hit_record: &HitRecord) -> Color {
+        Color::new()
+    }
+
+    // The BSDF for light arriving from `wi` and leaving along `wo`, without
+    // the cosine term, and the lobes that contribute to it. Delta lobes are
+    // left out. The default is a Lambertian with the albedo of the material.
+    fn eval(
+        &self,
+        resources: &Resources,
+        hit_record: &HitRecord,
+        wo: &Direction,
+        wi: &Direction,
+    ) -> (Color, Lobe) {
+        if same_hemisphere(wo, wi, &hit_record.normal) {
+            (
+                self.albedo(resources, hit_record) / PI,
+                Lobe::DIFFUSE | Lobe::REFLECTION,
+            )
+        } else {
+            (Color::new(), Lobe::NONE)
+        }
+    }
+
+    // Solid angle density of `sample` returning `wi` for `wo`, and the lobes
+    // that could have sampled it. Zero when `sample` can't return `wi`, light
+    // samples are then not weighted against their bounces.
+    fn pdf(
+        &self,
+        _: &Resources,
+        _hit_record: &HitRecord,
+        _wo: &Direction,
+        _wi: &Direction,
+    ) -> (f32, Lobe) {
+        (0., Lobe::NONE)
+    }
+
+    // Instances with an emissive material are sampled as lights.
+    fn is_emissive(&self, _: &Resources) -> bool {
+        false
+    }
+
+    // Materials that only scatter into delta lobes can't be lit by light
+    // samples, every other material is and ends its path when `sample`
+    // fails to return a density.
+    fn is_delta(&self) -> bool {
+        false
+    }
 }
@@ -1,3 +1,4 @@
+use serde::Deserialize;
 use slotmap::DefaultKey;
 
 use super::brdf::*;
@@ -7,13 +8,14 @@ use super::onb::*;
 use super::rand;
 use super::ray::Ray;
 use super::resources::Resources;
-use super::types::Color;
+use super::types::{Color, Direction};
 use super::vec::*;
-use crate::disney_brdf_sample::sample_disney_bsdf;
+use crate::disney_brdf_pdf::{pdf_disney, pdf_disney_micro_facet_anisotropic, pdf_lambert};
 use crate::disney_brdf_sample::sample_disney_diffuse;
+use crate::disney_brdf_sample::{sample_disney_bsdf, sample_disney_micro_facet_anisotropic};
+use crate::math_utils::same_hemisphere;
 use crate::rand::float;
 use crate::rand::sphere;
-use std::f32::consts::PI;
 pub struct DiffuseMaterial {
     albedo: DefaultKey,
 }
@@ -29,18 +31,38 @@ impl Material for DiffuseMaterial {
         1
     }
 
-    fn evaluate(&self, resources: &Resources, hit_record: &HitRecord) -> Bounce {
-        let onb = OrthoNormalBasis::from_w(&hit_record.normal);
-        let dir = onb.local(&rand::cosine());
-        let cos_theta = saturate(dot(&dir, &hit_record.normal));
+    fn sample(&self, resources: &Resources, hit_record: &HitRecord, wo: &Direction) -> Bounce {
+        let onb = OrthoNormalBasis::from_w(&hit_record.forward_normal(wo));
+        let wi = onb.local(&rand::cosine());
 
-        let color = resources.texture(self.albedo).sample(
+        // The cosine and 1/pi of the Lambertian BRDF cancel against the
+        // density of the cosine weighted direction.
+        let color = self.albedo(resources, hit_record);
+
+        Bounce::new(&wi, &color)
+            .with_pdf(pdf_lambert(&wi, wo, &hit_record.normal))
+            .with_lobe(Lobe::DIFFUSE | Lobe::REFLECTION)
+    }
+
+    fn albedo(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
+        hit_record.tint(resources.texture(self.albedo).sample(
             resources,
             &hit_record.uv,
             &hit_record.position(),
-        ) / PI;
+        ))
+    }
 
-        Bounce::new(&dir, &color)
+    fn pdf(
+        &self,
+        _: &Resources,
+        hit_record: &HitRecord,
+        wo: &Direction,
+        wi: &Direction,
+    ) -> (f32, Lobe) {
+        match pdf_lambert(wi, wo, &hit_record.normal) {
+            pdf if pdf > 0. => (pdf, Lobe::DIFFUSE | Lobe::REFLECTION),
+            _ => (0., Lobe::NONE),
+        }
     }
 }
 
@@ -48,24 +70,35 @@ pub struct MirrorMaterial {
     albedo: DefaultKey,
 }
 
-// impl Material for MirrorMaterial {
-//     fn uid(&self) -> usize {
-//         2
-//     }
+// A perfect specular conductor, it reflects everything about the normal.
+impl Material for MirrorMaterial {
+    fn uid(&self) -> usize {
+        2
+    }
+
+    fn sample(&self, resources: &Resources, hit_record: &HitRecord, wo: &Direction) -> Bounce {
+        let normal = hit_record.forward_normal(wo);
+        let wi = reflect(&-wo, &normal);
+        Bounce::new(&wi, &self.albedo(resources, hit_record))
+            .with_lobe(Lobe::SPECULAR | Lobe::REFLECTION)
+    }
 
-//     fn scatter(&self, _: &Resources, hit_record: &HitRecord) -> Bounce {
-//         let new_origin = hit_record.position() + hit_record.normal * 0.05;
-//         let out_dir = reflect(&hit_record.ray_direction(), &hit_record.normal);
-//         let ray = Ray::new(&new_origin, &out_dir);
-//         Bounce::new(&ray, 1.0)
-//     }
+    fn eval(&self, _: &Resources, _: &HitRecord, _: &Direction, _: &Direction) -> (Color, Lobe) {
+        (Color::new(), Lobe::NONE)
+    }
 
-//     fn evaluate(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
-//         resources
-//             .texture(self.albedo)
-//             .sample(resources, &hit_record.uv, &hit_record.position())
-//     }
-// }
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
+        hit_record.tint(resources.texture(self.albedo).sample(
+            resources,
+            &hit_record.uv,
+            &hit_record.position(),
+        ))
+    }
+}
 
 impl MirrorMaterial {
     pub fn new(albedo: DefaultKey) -> Self {
@@ -84,41 +117,49 @@ impl TranslucentMaterial {
     }
 }
 
-// impl Material for TranslucentMaterial {
-//     fn uid(&self) -> usize {
-//         3
-//     }
+// A smooth dielectric. It reflects or refracts with the chance given by the
+// Fresnel equations, and always reflects under total internal reflection.
+impl Material for TranslucentMaterial {
+    fn uid(&self) -> usize {
+        3
+    }
 
-//     fn scatter(&self, _: &Resources, hit_record: &HitRecord) -> Bounce {
-//         let ratio = if hit_record.front_facing {
-//             1.0 / self.ior
-//         } else {
-//             self.ior
-//         };
+    fn sample(&self, resources: &Resources, hit_record: &HitRecord, wo: &Direction) -> Bounce {
+        let normal = hit_record.forward_normal(wo);
+        let eta = if hit_record.front_facing {
+            self.ior
+        } else {
+            1.0 / self.ior
+        };
 
-//         let cos_theta = dot(&(-hit_record.ray_direction()), &hit_record.normal).min(1.0);
-//         let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let albedo = self.albedo(resources, hit_record);
+        let reflectance = fresnel_dielectric(dot(wo, &normal), eta);
+        if float() < reflectance {
+            let wi = reflect(&-wo, &normal);
+            return Bounce::new(&wi, &albedo).with_lobe(Lobe::SPECULAR | Lobe::REFLECTION);
+        }
 
-//         let cannot_refract = ratio * sin_theta > 1.0;
-//         let f = rand::float();
-//         let wo = if cannot_refract || fresnel_schlick_reflectance(cos_theta, ratio) > f {
-//             reflect(hit_record.ray_direction(), &hit_record.normal)
-//         } else {
-//             refract_glsl(hit_record.ray_direction(), &hit_record.normal, ratio)
-//         };
+        // Radiance is compressed into a smaller solid angle on the denser side.
+        let wi = normalize(&refract_glsl(&-wo, &normal, 1.0 / eta));
+        Bounce::new(&wi, &(albedo / (eta * eta))).with_lobe(Lobe::SPECULAR | Lobe::TRANSMISSION)
+    }
 
-//         let new_origin = hit_record.position() + wo * 0.05;
+    fn eval(&self, _: &Resources, _: &HitRecord, _: &Direction, _: &Direction) -> (Color, Lobe) {
+        (Color::new(), Lobe::NONE)
+    }
 
-//         let ray = Ray::new(&new_origin, &wo);
-//         Bounce::new(&ray, 1.0)
-//     }
+    fn is_delta(&self) -> bool {
+        true
+    }
 
-//     fn evaluate(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
-//         resources
-//             .texture(self.albedo)
-//             .sample(resources, &hit_record.uv, &hit_record.position())
-//     }
-// }
+    fn albedo(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
+        hit_record.tint(resources.texture(self.albedo).sample(
+            resources,
+            &hit_record.uv,
+            &hit_record.position(),
+        ))
+    }
+}
 
 pub struct PBRMaterial {
     pub albedo: DefaultKey,
@@ -152,18 +193,11 @@ impl PBRMaterial {
     }
 }
 
-impl Material for PBRMaterial {
-    fn uid(&self) -> usize {
-        4
-    }
-
-    fn evaluate(&self, resources: &Resources, hit_record: &HitRecord) -> Bounce {
-        let base_color = resources.texture(self.albedo).sample(
-            resources,
-            &hit_record.uv,
-            &hit_record.position(),
-        );
+impl PBRMaterial {
+    // Below this the GGX distribution is too sharp to sample reliably.
+    const MIN_ROUGHNESS: f32 = 0.03;
 
+    fn roughness_and_metal(&self, resources: &Resources, hit_record: &HitRecord) -> (f32, f32) {
         let roughness = resources
             .texture(self.roughness)
             .sample(resources, &hit_record.uv, &hit_record.position())
@@ -174,34 +208,63 @@ impl Material for PBRMaterial {
             .sample(resources, &hit_record.uv, &hit_record.position())
             .x();
 
-        let v = -hit_record.ray_direction();
-        let r_brdf = float();
-        let (wi, color) = if r_brdf < 0.5 {
-            if 2.0 * r_brdf < self.transmission {
-                sample_microfacet_transmission_brdf(
-                    &v,
-                    &hit_record.normal,
-                    &base_color,
-                    metal,
-                    roughness,
-                    self.fresnel_reflectance,
-                    self.ior,
-                )
-            } else {
-                sample_micro_facet_isotropic_specular_brdf(
-                    &v,
-                    &hit_record.normal,
-                    &base_color,
-                    metal,
-                    roughness,
-                    self.fresnel_reflectance,
-                )
-            }
+        (roughness.max(Self::MIN_ROUGHNESS), metal)
+    }
+
+    // Index of refraction on the other side of the surface over the one on
+    // the side of the ray.
+    fn eta(&self, hit_record: &HitRecord) -> f32 {
+        if hit_record.front_facing {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
+}
+
+// An opaque layer with a diffuse and a GGX lobe, blended with a rough
+// dielectric by `transmission`. Metals don't transmit.
+impl Material for PBRMaterial {
+    fn uid(&self) -> usize {
+        4
+    }
+
+    fn sample(&self, resources: &Resources, hit_record: &HitRecord, wo: &Direction) -> Bounce {
+        let (roughness, metal) = self.roughness_and_metal(resources, hit_record);
+        let normal = hit_record.forward_normal(wo);
+        let dielectric = self.transmission * (1.0 - metal);
+
+        // The opaque layer picks its diffuse or its specular lobe with equal
+        // chance, and the sample is weighted by the density of all lobes.
+        let sampled = if float() < dielectric {
+            sample_rough_dielectric(wo, &normal, roughness, self.eta(hit_record)).map(|wi| {
+                if dot(&wi, &normal) < 0.0 {
+                    (wi, Lobe::GLOSSY | Lobe::TRANSMISSION)
+                } else {
+                    (wi, Lobe::GLOSSY | Lobe::REFLECTION)
+                }
+            })
+        } else if float() < 0.5 {
+            let wi = OrthoNormalBasis::from_w(&normal).local(&rand::cosine());
+            Some((wi, Lobe::DIFFUSE | Lobe::REFLECTION))
         } else {
-            sample_diffuse_brdf(&v, &base_color, metal, self.fresnel_reflectance)
+            let h = sample_ggx_half_vector(&normal, roughness);
+            let wi = reflect(&-wo, &h);
+            (dot(&wi, &normal) > 0.0).then_some((wi, Lobe::GLOSSY | Lobe::REFLECTION))
+        };
+
+        let Some((wi, lobe)) = sampled else {
+            return Bounce::new(&normal, &Color::new());
         };
 
-        Bounce::new(&wi, &(color * 2.0))
+        let (pdf, _) = self.pdf(resources, hit_record, wo, &wi);
+        if pdf <= 0.0 {
+            return Bounce::new(&wi, &Color::new());
+        }
+
+        let (f, _) = self.eval(resources, hit_record, wo, &wi);
+        let color = f * (dot(&wi, &normal).abs() / pdf);
+        Bounce::new(&wi, &color).with_pdf(pdf).with_lobe(lobe)
     }
 
     fn emit(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
@@ -209,4 +272,685 @@ impl Material for PBRMaterial {
             .texture(self.emission)
             .sample(resources, &hit_record.uv, &hit_record.position())
     }
+
+    fn eval(
+        &self,
+        resources: &Resources,
+        hit_record: &HitRecord,
+        wo: &Direction,
+        wi: &Direction,
+    ) -> (Color, Lobe) {
+        let (roughness, metal) = self.roughness_and_metal(resources, hit_record);
+        let normal = hit_record.forward_normal(wo);
+        let dielectric = self.transmission * (1.0 - metal);
+        let albedo = self.albedo(resources, hit_record);
+        let bsdf = if dielectric > 0.0 {
+            evaluate_rough_dielectric(wo, wi, &normal, roughness, self.eta(hit_record))
+        } else {
+            0.0
+        };
+
+        // Light that made it through is tinted by the albedo.
+        if !same_hemisphere(wo, wi, &normal) {
+            if bsdf <= 0.0 {
+                return (Color::new(), Lobe::NONE);
+            }
+
+            let f = albedo * (dielectric * bsdf);
+            return (f, Lobe::GLOSSY | Lobe::TRANSMISSION);
+        }
+
+        let brdf = evaluate_microfacet_isotropic_brdf(
+            wi,
+            wo,
+            &normal,
+            &albedo,
+            metal,
+            roughness,
+            self.fresnel_reflectance,
+        );
+        let f = brdf * (1.0 - dielectric) + Color::splat(dielectric * bsdf);
+        (f, Lobe::DIFFUSE | Lobe::GLOSSY | Lobe::REFLECTION)
+    }
+
+    fn pdf(
+        &self,
+        resources: &Resources,
+        hit_record: &HitRecord,
+        wo: &Direction,
+        wi: &Direction,
+    ) -> (f32, Lobe) {
+        let (roughness, metal) = self.roughness_and_metal(resources, hit_record);
+        let normal = hit_record.forward_normal(wo);
+        let dielectric = self.transmission * (1.0 - metal);
+        let bsdf = if dielectric > 0.0 {
+            pdf_rough_dielectric(wo, wi, &normal, roughness, self.eta(hit_record))
+        } else {
+            0.0
+        };
+
+        if !same_hemisphere(wo, wi, &normal) {
+            return match dielectric * bsdf {
+                pdf if pdf > 0.0 => (pdf, Lobe::GLOSSY | Lobe::TRANSMISSION),
+                _ => (0.0, Lobe::NONE),
+            };
+        }
+
+        let specular = pdf_ggx_reflection(wo, wi, &normal, roughness);
+        let opaque = 0.5 * pdf_lambert(wi, wo, &normal) + 0.5 * specular;
+        let pdf = (1.0 - dielectric) * opaque + dielectric * bsdf;
+        (pdf, Lobe::DIFFUSE | Lobe::GLOSSY | Lobe::REFLECTION)
+    }
+
+    fn is_emissive(&self, resources: &Resources) -> bool {
+        !resources.texture(self.emission).is_black()
+    }
+
+    fn albedo(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
+        hit_record.tint(resources.texture(self.albedo).sample(
+            resources,
+            &hit_record.uv,
+            &hit_record.position(),
+        ))
+    }
+}
+
+// Disney's principled BSDF. Every parameter is a texture whose first channel
+// is read, and those that are not set take Disney's defaults.
+pub struct DisneyMaterial {
+    pub base_color: DefaultKey,
+    pub metallic: Option<DefaultKey>,
+    pub roughness: Option<DefaultKey>,
+    pub anisotropic: Option<DefaultKey>,
+    pub sheen: Option<DefaultKey>,
+    pub sheen_tint: Option<DefaultKey>,
+    pub clearcoat: Option<DefaultKey>,
+    pub clearcoat_gloss: Option<DefaultKey>,
+    pub specular_tint: Option<DefaultKey>,
+    pub subsurface: Option<DefaultKey>,
+    pub spec_trans: Option<DefaultKey>,
+    pub emission: Option<DefaultKey>,
+    pub ior: f32,
+}
+
+impl DisneyMaterial {
+    pub fn new(base_color: DefaultKey) -> Self {
+        Self {
+            base_color,
+            metallic: None,
+            roughness: None,
+            anisotropic: None,
+            sheen: None,
+            sheen_tint: None,
+            clearcoat: None,
+            clearcoat_gloss: None,
+            specular_tint: None,
+            subsurface: None,
+            spec_trans: None,
+            emission: None,
+            ior: 1.5,
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: DefaultKey) -> Self {
+        self.metallic = Some(metallic);
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: DefaultKey) -> Self {
+        self.roughness = Some(roughness);
+        self
+    }
+
+    pub fn with_anisotropic(mut self, anisotropic: DefaultKey) -> Self {
+        self.anisotropic = Some(anisotropic);
+        self
+    }
+
+    pub fn with_sheen(mut self, sheen: DefaultKey) -> Self {
+        self.sheen = Some(sheen);
+        self
+    }
+
+    pub fn with_sheen_tint(mut self, sheen_tint: DefaultKey) -> Self {
+        self.sheen_tint = Some(sheen_tint);
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: DefaultKey) -> Self {
+        self.clearcoat = Some(clearcoat);
+        self
+    }
+
+    pub fn with_clearcoat_gloss(mut self, clearcoat_gloss: DefaultKey) -> Self {
+        self.clearcoat_gloss = Some(clearcoat_gloss);
+        self
+    }
+
+    pub fn with_specular_tint(mut self, specular_tint: DefaultKey) -> Self {
+        self.specular_tint = Some(specular_tint);
+        self
+    }
+
+    pub fn with_subsurface(mut self, subsurface: DefaultKey) -> Self {
+        self.subsurface = Some(subsurface);
+        self
+    }
+
+    pub fn with_spec_trans(mut self, spec_trans: DefaultKey) -> Self {
+        self.spec_trans = Some(spec_trans);
+        self
+    }
+
+    pub fn with_emission(mut self, emission: DefaultKey) -> Self {
+        self.emission = Some(emission);
+        self
+    }
+
+    pub fn with_ior(mut self, ior: f32) -> Self {
+        self.ior = ior;
+        self
+    }
+}
+
+impl DisneyMaterial {
+    // Below this the GGX distribution is too sharp to sample reliably.
+    const MIN_ROUGHNESS: f32 = 0.03;
+
+    pub fn parameters(&self, resources: &Resources, hit_record: &HitRecord) -> DisneyParameters {
+        let scalar = |key: Option<DefaultKey>, default: f32| {
+            key.map_or(default, |key| {
+                resources
+                    .texture(key)
+                    .sample(resources, &hit_record.uv, &hit_record.position())
+                    .x()
+                    .clamp(0.0, 1.0)
+            })
+        };
+
+        DisneyParameters {
+            base_color: self.albedo(resources, hit_record),
+            metallic: scalar(self.metallic, 0.0),
+            roughness: scalar(self.roughness, 0.5).max(Self::MIN_ROUGHNESS),
+            anisotropic: scalar(self.anisotropic, 0.0),
+            sheen: scalar(self.sheen, 0.0),
+            sheen_tint: scalar(self.sheen_tint, 0.5),
+            clearcoat: scalar(self.clearcoat, 0.0),
+            clearcoat_gloss: scalar(self.clearcoat_gloss, 1.0),
+            specular_tint: scalar(self.specular_tint, 0.0),
+            subsurface: scalar(self.subsurface, 0.0),
+            spec_trans: scalar(self.spec_trans, 0.0),
+            eta: if hit_record.front_facing {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+        }
+    }
+
+    // Lobes that can connect `wo` and `wi`.
+    fn lobes(parameters: &DisneyParameters, normal: &Direction, wi: &Direction) -> Lobe {
+        if dot(wi, normal) > 0.0 {
+            Lobe::DIFFUSE | Lobe::GLOSSY | Lobe::REFLECTION
+        } else if parameters.spec_trans > 0.0 && parameters.metallic < 1.0 {
+            Lobe::GLOSSY | Lobe::TRANSMISSION
+        } else {
+            Lobe::NONE
+        }
+    }
+}
+
+impl Material for DisneyMaterial {
+    fn uid(&self) -> usize {
+        5
+    }
+
+    fn sample(&self, resources: &Resources, hit_record: &HitRecord, wo: &Direction) -> Bounce {
+        let parameters = self.parameters(resources, hit_record);
+        let normal = hit_record.forward_normal(wo);
+        let (x, y) = direction_of_anisotropicity(&normal);
+        let Some((wi, lobe)) = sample_disney_bsdf(wo, &normal, &x, &y, &parameters) else {
+            return Bounce::new(&normal, &Color::new());
+        };
+
+        let pdf = pdf_disney(&wi, wo, &normal, &x, &y, &parameters);
+        if pdf <= 0.0 {
+            return Bounce::new(&wi, &Color::new());
+        }
+
+        let f = evaluate_disney_bsdf(&wi, wo, &normal, &x, &y, &parameters);
+        let color = f * (dot(&wi, &normal).abs() / pdf);
+        Bounce::new(&wi, &color).with_pdf(pdf).with_lobe(lobe)
+    }
+
+    fn emit(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
+        self.emission.map_or(Color::new(), |emission| {
+            resources
+                .texture(emission)
+                .sample(resources, &hit_record.uv, &hit_record.position())
+        })
+    }
+
+    fn eval(
+        &self,
+        resources: &Resources,
+        hit_record: &HitRecord,
+        wo: &Direction,
+        wi: &Direction,
+    ) -> (Color, Lobe) {
+        let parameters = self.parameters(resources, hit_record);
+        let normal = hit_record.forward_normal(wo);
+        let (x, y) = direction_of_anisotropicity(&normal);
+        let f = evaluate_disney_bsdf(wi, wo, &normal, &x, &y, &parameters);
+        (f, Self::lobes(&parameters, &normal, wi))
+    }
+
+    fn pdf(
+        &self,
+        resources: &Resources,
+        hit_record: &HitRecord,
+        wo: &Direction,
+        wi: &Direction,
+    ) -> (f32, Lobe) {
+        let parameters = self.parameters(resources, hit_record);
+        let normal = hit_record.forward_normal(wo);
+        let (x, y) = direction_of_anisotropicity(&normal);
+        match pdf_disney(wi, wo, &normal, &x, &y, &parameters) {
+            pdf if pdf > 0.0 => (pdf, Self::lobes(&parameters, &normal, wi)),
+            _ => (0.0, Lobe::NONE),
+        }
+    }
+
+    fn is_emissive(&self, resources: &Resources) -> bool {
+        self.emission
+            .is_some_and(|emission| !resources.texture(emission).is_black())
+    }
+
+    fn albedo(&self, resources: &Resources, hit_record: &HitRecord) -> Color {
+        hit_record.tint(resources.texture(self.base_color).sample(
+            resources,
+            &hit_record.uv,
+            &hit_record.position(),
+        ))
+    }
+}
+
+// Metals with a measured complex index of refraction, sampled at the red,
+// green and blue primaries.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metal {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+    Chromium,
+}
+
+impl Metal {
+    // The real and imaginary parts of the index of refraction.
+    pub fn eta_and_k(self) -> (Color, Color) {
+        let (eta, k) = match self {
+            Metal::Gold => ([0.143119, 0.374957, 1.44248], [3.98316, 2.38572, 1.60322]),
+            Metal::Silver => ([0.155265, 0.116723, 0.138342], [4.82835, 3.12225, 2.14696]),
+            Metal::Copper => ([0.200438, 0.924033, 1.10221], [3.91295, 2.45285, 2.14219]),
+            Metal::Aluminium => ([1.65746, 0.880369, 0.521229], [9.22387, 6.26952, 4.837]),
+            Metal::Chromium => ([4.36968, 2.9167, 1.6547], [5.20637, 4.23132, 3.75469]),
+        };
+        (Color::from_values(eta), Color::from_values(k))
+    }
+}
+
+// A metal that reflects with the Fresnel equations of its complex index of
+// refraction off a GGX distribution of microfacets, which is stretched along
+// the tangent by `anisotropic`.
+pub struct ConductorMaterial {
+    pub eta: Color,
+    pub k: Color,
+    pub roughness: DefaultKey,
+    pub anisotropic: Option<DefaultKey>,
+}
+
+impl ConductorMaterial {
+    pub fn new(eta: &Color, k: &Color, roughness: DefaultKey) -> Self {
+        Self {
+            eta: *eta,
+            k: *k,
+            roughness,
+            anisotropic: None,
+        }
+    }
+
+    pub fn from_metal(metal: Metal, roughness: DefaultKey) -> Self {
+        let (eta, k) = metal.eta_and_k();
+        Self::new(&eta, &k, roughness)
+    }
+
+    pub fn with_anisotropic(mut self, anisotropic: DefaultKey) -> Self {
+        self.anisotropic = Some(anisotropic);
+        self
+    }
+}
+
+impl ConductorMaterial {
+    // Below this the GGX distribution is too sharp to sample reliably.
+    const MIN_ROUGHNESS: f32 = 0.03;
+
+    fn roughness_and_anisotropic(
+        &self,
+        resources: &Resources,
+        hit_record: &HitRecord,
+    ) -> (f32, f32) {
+        let sample = |key: DefaultKey| {
+            resources
+                .texture(key)
+                .sample(resources, &hit_record.uv, &hit_record.position())
+                .x()
+                .clamp(0.0, 1.0)
+        };
+
+        let roughness = sample(self.roughness).max(Self::MIN_ROUGHNESS);
+        (roughness, self.anisotropic.map_or(0.0, sample))
+    }
+
+    fn fresnel(&self, cos_theta: f32) -> Color {
+        Color::from_values(
+            [0, 1, 2].map(|i| fresnel_conductor(cos_theta, self.eta.data[i], self.k.data[i])),
+        )
+    }
+}
+
+impl Material for ConductorMaterial {
+    fn uid(&self) -> usize {
+        6
+    }
+
+    fn sample(&self, resources: &Resources, hit_record: &HitRecord, wo: &Direction) -> Bounce {
+        let (roughness, anisotropic) = self.roughness_and_anisotropic(resources, hit_record);
+        let normal = hit_record.forward_normal(wo);
+        let (x, y) = direction_of_anisotropicity(&normal);
+        let wi = sample_disney_micro_facet_anisotropic(wo, &normal, &x, &y, roughness, anisotropic);
+        let (pdf, _) = self.pdf(resources, hit_record, wo, &wi);
+        if pdf <= 0.0 {
+            return Bounce::new(&wi, &Color::new());
+        }
+
+        let (f, _) = self.eval(resources, hit_record, wo, &wi);
+        let color = f * (dot(&wi, &normal) / pdf);
+        Bounce::new(&wi, &color)
+            .with_pdf(pdf)
+            .with_lobe(Lobe::GLOSSY | Lobe::REFLECTION)
+    }
+
+    // The reflectance at normal incidence.
+    fn albedo(&self, _: &Resources, _: &HitRecord) -> Color {
+        self.fresnel(1.0)
+    }
+
+    fn eval(
+        &self,
+        resources: &Resources,
+        hit_record: &HitRecord,
+        wo: &Direction,
+        wi: &Direction,
+    ) -> (Color, Lobe) {
+        let normal = hit_record.forward_normal(wo);
+        let n_dot_l = dot(&normal, wi);
+        let n_dot_v = dot(&normal, wo);
+        if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+            return (Color::new(), Lobe::NONE);
+        }
+
+        let (roughness, anisotropic) = self.roughness_and_anisotropic(resources, hit_record);
+        let (x, y) = direction_of_anisotropicity(&normal);
+        let (ax, ay) = anisotropic_alphas(roughness, anisotropic);
+        let h = normalize(&(*wi + *wo));
+        let d = gtr2_anisotropic(dot(&normal, &h), dot(&h, &x), dot(&h, &y), ax, ay);
+        let g = smith_g_ggx_aniso(n_dot_l, dot(wi, &x), dot(wi, &y), ax, ay)
+            * smith_g_ggx_aniso(n_dot_v, dot(wo, &x), dot(wo, &y), ax, ay);
+        let f = self.fresnel(dot(wo, &h));
+        (f * (d * g), Lobe::GLOSSY | Lobe::REFLECTION)
+    }
+
+    fn pdf(
+        &self,
+        resources: &Resources,
+        hit_record: &HitRecord,
+        wo: &Direction,
+        wi: &Direction,
+    ) -> (f32, Lobe) {
+        let normal = hit_record.forward_normal(wo);
+        if dot(&normal, wi) <= 0.0 {
+            return (0.0, Lobe::NONE);
+        }
+
+        let (roughness, anisotropic) = self.roughness_and_anisotropic(resources, hit_record);
+        let (x, y) = direction_of_anisotropicity(&normal);
+        let pdf =
+            pdf_disney_micro_facet_anisotropic(wi, wo, &normal, &x, &y, roughness, anisotropic);
+        (pdf, Lobe::GLOSSY | Lobe::REFLECTION)
+    }
+}
+
+#[cfg(test)]
+mod materials_tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::intersection::Intersection;
+    use crate::texture::SolidColorTexture;
+    use crate::types::Position;
+
+    // A hit on the front of the XZ plane, seen at 45 degrees.
+    fn hit_record() -> HitRecord {
+        let direction = normalize(&Direction::from_values([1., -1., 0.]));
+        HitRecord {
+            intersection: Intersection::new(
+                &Ray::new(&Position::from_values([-1., 1., 0.]), &direction),
+                2f32.sqrt(),
+                0,
+                &Default::default(),
+            ),
+            normal: Direction::from_values([0., 1., 0.]),
+            front_facing: true,
+            ..Default::default()
+        }
+    }
+
+    // The bounce weight is the BSDF times the cosine over the density.
+    fn check_samples(material: &dyn Material, resources: &Resources, hit_record: &HitRecord) {
+        let wo = hit_record.wo();
+        for _ in 0..100 {
+            let bounce = material.sample(resources, hit_record, &wo);
+            if bounce.pdf > 0. {
+                assert!(!bounce.lobe.is_specular());
+                let (f, lobe) = material.eval(resources, hit_record, &wo, &bounce.wi);
+                let (pdf, _) = material.pdf(resources, hit_record, &wo, &bounce.wi);
+                assert!(lobe.contains(bounce.lobe));
+                assert!((pdf - bounce.pdf).abs() < 0.001 * pdf);
+                let expected = f * (dot(&bounce.wi, &hit_record.normal).abs() / bounce.pdf);
+                assert!(length(&(bounce.color - expected)) < 0.001 * length(&expected).max(1.));
+            }
+        }
+    }
+
+    // Integral of the density over the upper hemisphere, or the whole sphere.
+    fn integrate_pdf(
+        material: &dyn Material,
+        resources: &Resources,
+        hit_record: &HitRecord,
+        sphere: bool,
+    ) -> f32 {
+        let wo = hit_record.wo();
+        let extent = if sphere { PI } else { PI / 2. };
+        let steps = 200;
+        let mut integral = 0.;
+        for j in 0..steps {
+            let theta = (j as f32 + 0.5) / steps as f32 * extent;
+            for i in 0..steps {
+                let phi = (i as f32 + 0.5) / steps as f32 * 2. * PI;
+                let wi = Direction::from_values([
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                ]);
+                let solid_angle = theta.sin() * (extent / steps as f32) * (2. * PI / steps as f32);
+                integral += material.pdf(resources, hit_record, &wo, &wi).0 * solid_angle;
+            }
+        }
+        integral
+    }
+
+    #[test]
+    fn test_pbr_pdf() {
+        let mut resources = Resources::default();
+        let albedo = resources.add_texture(SolidColorTexture::new(&Color::splat(0.8)));
+        let roughness = resources.add_texture(SolidColorTexture::new(&Color::splat(0.3)));
+        let black = resources.add_texture(SolidColorTexture::new(&Color::new()));
+        let material = PBRMaterial::new(albedo, roughness, black, black, 1.5, 0., 0.5);
+
+        let hit_record = hit_record();
+        let wo = hit_record.wo();
+        check_samples(&material, &resources, &hit_record);
+        for _ in 0..10 {
+            let bounce = material.sample(&resources, &hit_record, &wo);
+            assert!(bounce.pdf <= 0. || bounce.lobe.contains(Lobe::REFLECTION));
+        }
+
+        // The density integrates to one over the hemisphere.
+        let integral = integrate_pdf(&material, &resources, &hit_record, false);
+        assert!((integral - 1.).abs() < 0.02);
+        assert!(!material.is_emissive(&resources));
+
+        // Light from below the surface doesn't reach `wo`.
+        let below = Direction::from_values([0., -1., 0.]);
+        assert_eq!(
+            material.eval(&resources, &hit_record, &wo, &below).1,
+            Lobe::NONE
+        );
+        assert_eq!(material.pdf(&resources, &hit_record, &wo, &below).0, 0.);
+
+        // Transmissive ones also sample the light coming through.
+        let material = PBRMaterial::new(albedo, roughness, black, black, 1.5, 0.5, 0.5);
+        check_samples(&material, &resources, &hit_record);
+        let (f, lobe) = material.eval(&resources, &hit_record, &wo, &below);
+        assert_eq!(lobe, Lobe::GLOSSY | Lobe::TRANSMISSION);
+        assert!(f.x() > 0.);
+        let integral = integrate_pdf(&material, &resources, &hit_record, true);
+        assert!(integral > 0.95 && integral < 1.01);
+    }
+
+    #[test]
+    fn test_delta_materials() {
+        let mut resources = Resources::default();
+        let white = resources.add_texture(SolidColorTexture::new(&Color::ones()));
+        let hit_record = hit_record();
+        let wo = hit_record.wo();
+        let mirrored = Direction::from_values([-wo.x(), wo.y(), wo.z()]);
+
+        let mirror = MirrorMaterial::new(white);
+        let bounce = mirror.sample(&resources, &hit_record, &wo);
+        assert!(length(&(bounce.wi - mirrored)) < 0.0001);
+        assert_eq!(bounce.pdf, 0.);
+        assert_eq!(bounce.lobe, Lobe::SPECULAR | Lobe::REFLECTION);
+        assert_eq!(
+            mirror.eval(&resources, &hit_record, &wo, &mirrored).1,
+            Lobe::NONE
+        );
+        assert!(mirror.is_delta());
+        assert!(!DiffuseMaterial::new(white).is_delta());
+
+        // Entering glass most light is refracted towards the normal.
+        let glass = TranslucentMaterial::new(white, 1.5);
+        let mut refracted = 0;
+        for _ in 0..1000 {
+            let bounce = glass.sample(&resources, &hit_record, &wo);
+            assert!(bounce.lobe.is_specular());
+            assert_eq!(bounce.pdf, 0.);
+            if bounce.lobe.contains(Lobe::TRANSMISSION) {
+                refracted += 1;
+                let sin_theta = (1. - bounce.wi.y() * bounce.wi.y()).sqrt();
+                assert!((sin_theta * 1.5 - 0.5f32.sqrt()).abs() < 0.001);
+                assert!((bounce.color.x() - 1. / 2.25).abs() < 0.001);
+            } else {
+                assert!(length(&(bounce.wi - mirrored)) < 0.0001);
+            }
+        }
+        assert!(refracted > 900);
+
+        // Leaving it at the same angle is past the critical angle.
+        let inside = HitRecord {
+            front_facing: false,
+            normal: Direction::from_values([0., -1., 0.]),
+            ..hit_record
+        };
+        for _ in 0..100 {
+            let bounce = glass.sample(&resources, &inside, &wo);
+            assert_eq!(bounce.lobe, Lobe::SPECULAR | Lobe::REFLECTION);
+        }
+    }
+
+    #[test]
+    fn test_conductor() {
+        // At normal incidence the reflectance is ((n - 1)² + k²) / ((n + 1)² + k²).
+        let (eta, k) = Metal::Gold.eta_and_k();
+        let f0 = |n: f32, k: f32| ((n - 1.) * (n - 1.) + k * k) / ((n + 1.) * (n + 1.) + k * k);
+        let mut resources = Resources::default();
+        let roughness = resources.add_texture(SolidColorTexture::new(&Color::splat(0.3)));
+        let half = resources.add_texture(SolidColorTexture::new(&Color::splat(0.5)));
+        let material = ConductorMaterial::from_metal(Metal::Gold, roughness).with_anisotropic(half);
+        let hit_record = hit_record();
+        let albedo = material.albedo(&resources, &hit_record);
+        assert!((albedo.x() - f0(eta.x(), k.x())).abs() < 0.0001);
+        assert!(albedo.x() > albedo.z());
+        assert!((fresnel_conductor(0., eta.z(), k.z()) - 1.).abs() < 0.0001);
+
+        check_samples(&material, &resources, &hit_record);
+        let integral = integrate_pdf(&material, &resources, &hit_record, false);
+        assert!(integral > 0.9 && integral < 1.01);
+
+        let wo = hit_record.wo();
+        let below = Direction::from_values([0., -1., 0.]);
+        assert_eq!(
+            material.eval(&resources, &hit_record, &wo, &below).1,
+            Lobe::NONE
+        );
+    }
+
+    #[test]
+    fn test_disney_pdf() {
+        let mut resources = Resources::default();
+        let base_color =
+            resources.add_texture(SolidColorTexture::new(&Color::from_values([0.8, 0.4, 0.2])));
+        let half = resources.add_texture(SolidColorTexture::new(&Color::splat(0.5)));
+        let material = DisneyMaterial::new(base_color)
+            .with_roughness(half)
+            .with_anisotropic(half)
+            .with_sheen(half)
+            .with_clearcoat(half)
+            .with_subsurface(half);
+
+        let hit_record = hit_record();
+        let wo = hit_record.wo();
+        check_samples(&material, &resources, &hit_record);
+        let integral = integrate_pdf(&material, &resources, &hit_record, false);
+        assert!(integral > 0.9 && integral < 1.01);
+        assert!(!material.is_emissive(&resources));
+
+        // Opaque materials don't let light through, transmissive ones do and
+        // sample it both ways.
+        let below = normalize(&Direction::from_values([0.3, -1., 0.]));
+        assert_eq!(
+            material.eval(&resources, &hit_record, &wo, &below).1,
+            Lobe::NONE
+        );
+
+        let material = material.with_spec_trans(half);
+        check_samples(&material, &resources, &hit_record);
+        let (f, lobe) = material.eval(&resources, &hit_record, &wo, &below);
+        assert_eq!(lobe, Lobe::GLOSSY | Lobe::TRANSMISSION);
+        assert!(f.x() > 0.);
+        let integral = integrate_pdf(&material, &resources, &hit_record, true);
+        assert!(integral > 0.9 && integral < 1.01);
+    }
 }
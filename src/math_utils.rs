@@ -27,6 +27,22 @@ impl Saturate for f32 {
     }
 }
 
+pub fn degrees_to_radians(degrees: f32) -> f32 {
+    degrees * std::f32::consts::PI / 180.
+}
+
+// Multiple importance sampling weight of a sample taken with density `pdf`
+// when `other_pdf` could also have produced it, Veach's power heuristic.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b > 0. {
+        a / (a + b)
+    } else {
+        0.
+    }
+}
+
 pub fn mix(a: f32, b: f32, v: f32) -> f32 {
     a * (1f32 - v) + b * v
 }
@@ -0,0 +1,135 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use slotmap::DefaultKey;
+
+use super::environment_map::EnvironmentMap;
+use super::math_utils::power_heuristic;
+use super::ray::Ray;
+use super::raytracer::{MissShader, ShaderContext};
+use super::shader_binding_table::Payload;
+use super::types::*;
+use super::vec::*;
+
+// Blends from white at the horizon to light blue at the zenith.
+pub struct GradientMissShader {}
+
+impl MissShader for GradientMissShader {
+    fn miss(&self, _: &ShaderContext, ray: &Ray, payload: &mut Payload) {
+        let di = 0.5 * ray.dir.y() + 1.;
+        let c = Color::from_values([1.0, 1.0, 1.0]) * (1.0 - di)
+            + Color::from_values([0.5, 0.7, 1.0]) * di;
+        payload.radiance = payload.radiance + payload.throughput * c;
+    }
+}
+
+// The same radiance from every direction.
+pub struct ConstantMissShader {
+    color: Color,
+}
+
+impl ConstantMissShader {
+    pub fn new(color: &Color) -> Self {
+        Self { color: *color }
+    }
+}
+
+impl MissShader for ConstantMissShader {
+    fn miss(&self, _: &ShaderContext, _: &Ray, payload: &mut Payload) {
+        payload.radiance = payload.radiance + payload.throughput * self.color;
+    }
+}
+
+// Looks up the ray direction in a texture with an equirectangular mapping.
+// The center of the texture is in the -Z direction and +Y is up.
+pub struct EnvironmentMissShader {
+    texture: DefaultKey,
+    intensity: f32,
+}
+
+impl EnvironmentMissShader {
+    pub fn new(texture: DefaultKey) -> Self {
+        Self {
+            texture,
+            intensity: 1.,
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+// Texture coordinates of a direction in an equirectangular map.
+pub fn equirectangular_uv(direction: &Direction) -> TextureCoordinate {
+    let d = normalize(direction);
+    let u = 0.5 + d.x().atan2(-d.z()) / (2. * PI);
+    let v = 0.5 + d.y().clamp(-1., 1.).asin() / PI;
+    TextureCoordinate::from_values([u, v])
+}
+
+impl MissShader for EnvironmentMissShader {
+    fn miss(&self, context: &ShaderContext, ray: &Ray, payload: &mut Payload) {
+        let uv = equirectangular_uv(ray.direction());
+        let color =
+            context
+                .resources
+                .texture(self.texture)
+                .sample(context.resources, &uv, ray.direction());
+        payload.radiance = payload.radiance + payload.throughput * color * self.intensity;
+    }
+}
+
+// Draws an HDR environment map. When the map is also one of the scene lights
+// the hit shaders sample it directly, so rays that hit it after a bounce are
+// weighted against that with multiple importance sampling.
+pub struct EnvironmentMapMissShader {
+    map: Arc<EnvironmentMap>,
+}
+
+impl EnvironmentMapMissShader {
+    pub fn new(map: Arc<EnvironmentMap>) -> Self {
+        Self { map }
+    }
+}
+
+impl MissShader for EnvironmentMapMissShader {
+    fn miss(&self, context: &ShaderContext, ray: &Ray, payload: &mut Payload) {
+        let radiance = self.map.radiance(ray.direction());
+        let weight = match context.lights.environment() {
+            Some(environment) if payload.pdf > 0. => {
+                power_heuristic(payload.pdf, environment.pdf(ray.direction()))
+            }
+            _ => 1.,
+        };
+        payload.radiance = payload.radiance + payload.throughput * radiance * weight;
+    }
+}
+
+// Camera rays that miss see a transparent background, other rays still get
+// their light from the wrapped background.
+pub struct TransparentMissShader {
+    background: Box<dyn MissShader>,
+}
+
+impl TransparentMissShader {
+    pub fn new<M>(background: M) -> Self
+    where
+        M: MissShader + 'static,
+    {
+        Self {
+            background: Box::new(background),
+        }
+    }
+}
+
+impl MissShader for TransparentMissShader {
+    fn miss(&self, context: &ShaderContext, ray: &Ray, payload: &mut Payload) {
+        if payload.depth == 0 {
+            payload.alpha = 0.;
+        } else {
+            self.background.miss(context, ray, payload);
+        }
+    }
+}
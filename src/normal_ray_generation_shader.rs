@@ -0,0 +1,47 @@
+use super::rand;
+use crate::default_camera::DefaultCamera;
+use crate::ray::Ray;
+use crate::raytracer::{RayGenerationShader, ShaderContext, TraceSettings};
+use crate::types::*;
+
+// Debug integrator that shows world space normals of the first hit.
+pub struct NormalRayGenerator {
+    pub camera: DefaultCamera,
+}
+
+impl RayGenerationShader for NormalRayGenerator {
+    fn generate(
+        &self,
+        context: &ShaderContext,
+        settings: &TraceSettings,
+        x: u32,
+        y: u32,
+    ) -> (Color, f32) {
+        let (scene, resources) = (context.scene, context.resources);
+        let spp = settings.spp;
+        let mut color = Color::new();
+        let mut alpha = 0.;
+        for _ in 0..spp {
+            let u = (x as f32 + rand::float()) / settings.width as f32;
+            let v = (y as f32 + rand::float()) / settings.height as f32;
+            let ray = self.camera.ray(u, 1. - v);
+            if let Some((instance_id, hit)) = context
+                .ray_tracer
+                .intersect(&ray, scene, resources, 0.001, 1000.0)
+            {
+                let instance = scene.instance(instance_id as usize);
+                let normal = resources
+                    .hittable(instance.geometry_index)
+                    .normal(instance, &hit);
+                color = color + (normal + 1.0) * 0.5;
+                alpha += 1.;
+            }
+        }
+
+        (color / spp as f32, alpha / spp as f32)
+    }
+
+    fn primary_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        Some(self.camera.ray(u, v))
+    }
+}
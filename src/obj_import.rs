@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use slotmap::DefaultKey;
+
+use super::acceleration_structure::BuildQuality;
+use super::hittable::TriangleMesh;
+use super::materials::PBRMaterial;
+use super::resources::Resources;
+use super::texture::{ImageTexture, SolidColorTexture};
+use super::tone_mapping::luminance;
+use super::types::*;
+use super::vec::*;
+
+// Imports every object and group of an OBJ file as its own triangle mesh and
+// returns the keys of the meshes in file order. When `load_materials` is set
+// the MTL materials are added to `resources` and assigned per face, faces
+// without a material use the material of the instance.
+//
+// The PBR material has no opacity, per texel specular color or normal
+// perturbation. `d` is ignored, so partially transparent materials render
+// opaque, and a material with `map_Ks`, `map_d` or a bump map is rejected
+// with an error.
+pub fn import_obj(
+    path: &Path,
+    resources: &mut Resources,
+    load_materials: bool,
+    build_quality: BuildQuality,
+) -> Result<Vec<DefaultKey>, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let obj = parse_obj(&source).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut importer = Importer {
+        resources,
+        base_dir,
+        build_quality,
+        materials: Vec::new(),
+        material_names: HashMap::new(),
+        material_keys: HashMap::new(),
+        image_keys: HashMap::new(),
+    };
+
+    if load_materials {
+        for library in obj.material_libraries.iter() {
+            importer.load_material_library(library)?;
+        }
+    }
+
+    let mut meshes = Vec::new();
+    for model in obj.models.iter() {
+        if let Some(mesh) = importer.mesh(&obj, model, load_materials)? {
+            meshes.push(importer.resources.add_hittable(mesh));
+        }
+    }
+
+    Ok(meshes)
+}
+
+// The corner of a face, with zero based indices into the attribute lists.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Corner {
+    position: usize,
+    tex_coord: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct Face {
+    corners: Vec<Corner>,
+    material: Option<String>,
+    // 0 when the face is not part of a smoothing group.
+    smoothing_group: u32,
+}
+
+struct Model {
+    faces: Vec<Face>,
+}
+
+#[derive(Default)]
+struct Obj {
+    positions: Vec<Position>,
+    normals: Vec<Normal>,
+    tex_coords: Vec<TextureCoordinate>,
+    models: Vec<Model>,
+    material_libraries: Vec<String>,
+}
+
+fn parse_obj(source: &str) -> Result<Obj, String> {
+    let mut obj = Obj::default();
+    let mut faces = Vec::new();
+    let mut material = None;
+    // Files without smoothing statements are rendered smooth, which is what
+    // exporters that omit normals expect.
+    let mut smoothing_group = 1;
+
+    for (number, line) in source.lines().enumerate() {
+        let error = |what: &str| format!("Invalid {} on line {}", what, number + 1);
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let rest = line[keyword.len()..].trim();
+
+        match keyword {
+            "v" => obj.positions.push(Position::from_values(
+                parse_floats(words).ok_or_else(|| error("vertex"))?,
+            )),
+            "vn" => obj.normals.push(Normal::from_values(
+                parse_floats(words).ok_or_else(|| error("normal"))?,
+            )),
+            "vt" => {
+                // The optional third texture coordinate is ignored.
+                let values: Vec<f32> = words.take(2).filter_map(|w| w.parse().ok()).collect();
+                match values[..] {
+                    [u] => obj.tex_coords.push(TextureCoordinate::from_values([u, 0.])),
+                    [u, v] => obj.tex_coords.push(TextureCoordinate::from_values([u, v])),
+                    _ => return Err(error("texture coordinate")),
+                }
+            }
+            "f" => {
+                let corners = words
+                    .map(|word| parse_corner(word, &obj))
+                    .collect::<Option<Vec<Corner>>>()
+                    .ok_or_else(|| error("face"))?;
+                if corners.len() >= 3 {
+                    faces.push(Face {
+                        corners,
+                        material: material.clone(),
+                        smoothing_group,
+                    });
+                }
+            }
+            "o" | "g" if !faces.is_empty() => obj.models.push(Model {
+                faces: std::mem::take(&mut faces),
+            }),
+            "s" => {
+                smoothing_group = match rest {
+                    "off" => 0,
+                    _ => rest.parse().map_err(|_| error("smoothing group"))?,
+                }
+            }
+            "usemtl" => material = Some(rest.to_string()),
+            "mtllib" => obj
+                .material_libraries
+                .extend(words.map(|word| word.to_string())),
+            _ => (),
+        }
+    }
+
+    if !faces.is_empty() {
+        obj.models.push(Model { faces });
+    }
+
+    Ok(obj)
+}
+
+fn parse_floats<'a, const SIZE: usize>(
+    words: impl Iterator<Item = &'a str>,
+) -> Option<[f32; SIZE]> {
+    let mut values = [0.; SIZE];
+    let mut count = 0;
+    for (value, word) in values.iter_mut().zip(words) {
+        *value = word.parse().ok()?;
+        count += 1;
+    }
+
+    (count == SIZE).then_some(values)
+}
+
+// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`. Negative indices count back from
+// the last element read so far.
+fn parse_corner(word: &str, obj: &Obj) -> Option<Corner> {
+    let index = |value: Option<&str>, len: usize| -> Option<Option<usize>> {
+        match value {
+            None | Some("") => Some(None),
+            Some(value) => {
+                let index: i64 = value.parse().ok()?;
+                let index = if index < 0 {
+                    len as i64 + index
+                } else {
+                    index - 1
+                };
+                (0..len as i64)
+                    .contains(&index)
+                    .then_some(Some(index as usize))
+            }
+        }
+    };
+
+    let mut parts = word.split('/');
+    Some(Corner {
+        position: index(parts.next(), obj.positions.len())??,
+        tex_coord: index(parts.next(), obj.tex_coords.len())?,
+        normal: index(parts.next(), obj.normals.len())?,
+    })
+}
+
+// Decides which face corners can share a vertex. Corners with an explicit
+// normal are shared as is, the others only within their smoothing group.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Sharing {
+    Explicit,
+    Group(u32),
+    Face(usize),
+}
+
+struct Importer<'a> {
+    resources: &'a mut Resources,
+    base_dir: &'a Path,
+    build_quality: BuildQuality,
+    materials: Vec<tobj::Material>,
+    material_names: HashMap<String, usize>,
+    material_keys: HashMap<usize, DefaultKey>,
+    image_keys: HashMap<PathBuf, DefaultKey>,
+}
+
+impl<'a> Importer<'a> {
+    fn load_material_library(&mut self, library: &str) -> Result<(), String> {
+        let path = self.base_dir.join(library);
+        let (materials, _) = tobj::load_mtl(&path)
+            .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+        for material in materials {
+            self.material_names
+                .insert(material.name.clone(), self.materials.len());
+            self.materials.push(material);
+        }
+
+        Ok(())
+    }
+
+    fn mesh(
+        &mut self,
+        obj: &Obj,
+        model: &Model,
+        load_materials: bool,
+    ) -> Result<Option<TriangleMesh>, String> {
+        let mut vertices = HashMap::new();
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut generated_normals = Vec::new();
+        let mut tex_coords = Vec::new();
+        let mut indices = Vec::new();
+        let mut face_materials = Vec::new();
+
+        for (face_index, face) in model.faces.iter().enumerate() {
+            let mut face_indices = Vec::with_capacity(face.corners.len());
+            for corner in face.corners.iter() {
+                let sharing = match (corner.normal, face.smoothing_group) {
+                    (Some(_), _) => Sharing::Explicit,
+                    (None, 0) => Sharing::Face(face_index),
+                    (None, group) => Sharing::Group(group),
+                };
+                let index = *vertices.entry((*corner, sharing)).or_insert_with(|| {
+                    positions.push(obj.positions[corner.position]);
+                    normals.push(corner.normal.map_or(Normal::new(), |n| obj.normals[n]));
+                    generated_normals.push(corner.normal.is_none());
+                    tex_coords.push(
+                        corner
+                            .tex_coord
+                            .map_or(TextureCoordinate::new(), |t| obj.tex_coords[t]),
+                    );
+                    positions.len() as u32 - 1
+                });
+                face_indices.push(index);
+            }
+
+            let material = match (&face.material, load_materials) {
+                (Some(name), true) => self.material(name)?,
+                _ => None,
+            };
+
+            // Polygons are triangulated as fans around their first corner.
+            for i in 1..face_indices.len() - 1 {
+                let triangle = [face_indices[0], face_indices[i], face_indices[i + 1]];
+                let v0 = positions[triangle[0] as usize];
+                let face_normal = cross(
+                    &(positions[triangle[1] as usize] - v0),
+                    &(positions[triangle[2] as usize] - v0),
+                );
+                // The unnormalized face normal weights the average by area.
+                for index in triangle {
+                    if generated_normals[index as usize] {
+                        normals[index as usize] = normals[index as usize] + face_normal;
+                    }
+                }
+
+                indices.extend(triangle);
+                face_materials.push(material);
+            }
+        }
+
+        if indices.is_empty() {
+            return Ok(None);
+        }
+
+        let mesh = TriangleMesh::new_with_build_quality(
+            positions,
+            normals,
+            tex_coords,
+            indices,
+            self.build_quality,
+        );
+        if face_materials.iter().any(|material| material.is_some()) {
+            Ok(Some(mesh.with_materials(face_materials)))
+        } else {
+            Ok(Some(mesh))
+        }
+    }
+
+    fn material(&mut self, name: &str) -> Result<Option<DefaultKey>, String> {
+        let index = match self.material_names.get(name) {
+            Some(index) => *index,
+            None => return Ok(None),
+        };
+        if let Some(key) = self.material_keys.get(&index) {
+            return Ok(Some(*key));
+        }
+
+        let material = self.materials[index].clone();
+        let parameter = |name: &str| -> Option<&str> {
+            material.unknown_param.get(name).map(|value| value.as_str())
+        };
+        let scalar = |name: &str| parameter(name).and_then(|value| value.parse::<f32>().ok());
+
+        for (map, file_name) in [
+            ("map_Ks", &material.specular_texture),
+            ("map_d", &material.dissolve_texture),
+            ("map_Bump", &material.normal_texture),
+        ] {
+            if !file_name.is_empty() {
+                return Err(format!(
+                    "Unsupported map {} in material '{}'",
+                    map, material.name
+                ));
+            }
+        }
+
+        let diffuse = Color::from_values(material.diffuse);
+        let emission = parameter("Ke")
+            .and_then(|value| parse_floats(value.split_whitespace()))
+            .map_or(Color::new(), Color::from_values);
+        let metal = scalar("Pm").unwrap_or(0.);
+
+        let albedo = self.texture(Some(&material.diffuse_texture), &diffuse, true)?;
+        let emission = self.texture(parameter("map_Ke"), &emission, true)?;
+        let roughness = match (scalar("Pr"), parameter("map_Pr")) {
+            (None, None) if !material.shininess_texture.is_empty() => {
+                self.shininess_texture(&material.shininess_texture, material.shininess)?
+            }
+            (roughness, map) => {
+                let roughness = roughness.unwrap_or_else(|| phong_roughness(material.shininess));
+                self.texture(map, &Color::splat(roughness), false)?
+            }
+        };
+        let metal = self.texture(parameter("map_Pm"), &Color::splat(metal), false)?;
+
+        let ior = if material.optical_density > 0. {
+            material.optical_density
+        } else {
+            1.5
+        };
+        let key = self.resources.add_material(PBRMaterial::new(
+            albedo,
+            roughness,
+            metal,
+            emission,
+            ior,
+            0.,
+            luminance(&Color::from_values(material.specular)).clamp(0., 1.),
+        ));
+        self.material_keys.insert(index, key);
+        Ok(Some(key))
+    }
+
+    // Scales the Phong exponent by the map and converts every texel to a
+    // roughness, the conversion is not linear so it can't be a texture factor.
+    fn shininess_texture(&mut self, map: &str, shininess: f32) -> Result<DefaultKey, String> {
+        let file_name = map.split_whitespace().last().unwrap_or(map);
+        let path = self.base_dir.join(file_name);
+        let mut image = image::open(&path)
+            .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?
+            .to_rgba8();
+        for pixel in image.pixels_mut() {
+            let exponent = shininess * pixel[0] as f32 / 255.;
+            let roughness = (phong_roughness(exponent) * 255.).round() as u8;
+            *pixel = image::Rgba([roughness, roughness, roughness, 255]);
+        }
+
+        let image = self.resources.add_image(image);
+        Ok(self.resources.add_texture(ImageTexture::new(image)))
+    }
+
+    // A solid color when there is no map, otherwise the image scaled by `factor`.
+    fn texture(
+        &mut self,
+        map: Option<&str>,
+        factor: &Color,
+        srgb: bool,
+    ) -> Result<DefaultKey, String> {
+        // Map statements can carry options, the file name comes last.
+        let file_name = match map.and_then(|map| map.split_whitespace().last()) {
+            Some(file_name) => file_name,
+            None => return Ok(self.resources.add_texture(SolidColorTexture::new(factor))),
+        };
+
+        let path = self.base_dir.join(file_name);
+        let image = match self.image_keys.get(&path) {
+            Some(key) => *key,
+            None => {
+                let image = image::open(&path)
+                    .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+                let key = self.resources.add_image(image.to_rgba8());
+                self.image_keys.insert(path, key);
+                key
+            }
+        };
+
+        let texture = ImageTexture::new(image).with_factor(factor);
+        if srgb {
+            Ok(self.resources.add_texture(texture.with_srgb()))
+        } else {
+            Ok(self.resources.add_texture(texture))
+        }
+    }
+}
+
+// The Phong exponent is converted to a Beckmann width first, the roughness of
+// the PBR material is the square root of the width.
+fn phong_roughness(exponent: f32) -> f32 {
+    (2. / (exponent.max(0.) + 2.)).sqrt().sqrt()
+}
+
+#[cfg(test)]
+mod obj_import_tests {
+    use slotmap::DefaultKey;
+
+    use std::collections::HashMap;
+
+    use super::{import_obj, phong_roughness, Importer};
+    use crate::acceleration_structure::BuildQuality;
+    use crate::intersection::Intersection;
+    use crate::ray::Ray;
+    use crate::resources::Resources;
+    use crate::types::*;
+    use crate::vec::{XAccessor, ZAccessor};
+
+    const OBJ: &str = "mtllib test.mtl
+o first
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+usemtl red
+f 1 2 3 4
+o second
+v 0 0 1
+v 1 0 1
+v 0 1 1
+usemtl blue
+f -3 -2 -1
+usemtl missing
+f 5 6 7
+";
+
+    const MTL: &str = "newmtl red
+Kd 1 0 0
+Ns 100
+newmtl blue
+Kd 0 0 1
+Ke 1 1 1
+d 0.25
+";
+
+    #[test]
+    fn test_import_models() {
+        let dir = std::env::temp_dir().join("toy_tracer_test_import_models");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("test.obj"), OBJ).unwrap();
+        std::fs::write(dir.join("test.mtl"), MTL).unwrap();
+
+        let mut resources = Resources::default();
+        let meshes = import_obj(
+            &dir.join("test.obj"),
+            &mut resources,
+            true,
+            BuildQuality::default(),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(meshes.len(), 2);
+        // The second model indexes its own vertices, not the shared list.
+        let second = resources.hittable(meshes[1]);
+        assert_eq!(second.bounding_box().unwrap().min().z(), 1.);
+
+        let material = |mesh: DefaultKey, triangle: u32| {
+            let ray = Ray::new(&Position::new(), &Direction::from_values([0., 0., 1.]));
+            let intersection = Intersection::new(&ray, 1., triangle * 3, &Barycentrics::new());
+            resources.hittable(mesh).material(&intersection)
+        };
+        // The quad is split in two triangles that share the material.
+        assert!(material(meshes[0], 0).is_some());
+        assert_eq!(material(meshes[0], 0), material(meshes[0], 1));
+        assert!(material(meshes[1], 0).is_some());
+        assert_ne!(material(meshes[0], 0), material(meshes[1], 0));
+        assert!(material(meshes[1], 1).is_none());
+    }
+
+    #[test]
+    fn test_material_maps() {
+        let dir = std::env::temp_dir().join("toy_tracer_test_material_maps");
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255]))
+            .save(dir.join("shininess.png"))
+            .unwrap();
+        std::fs::write(dir.join("test.obj"), OBJ).unwrap();
+        let import = |mtl: &str| {
+            std::fs::write(dir.join("test.mtl"), mtl).unwrap();
+            let mut resources = Resources::default();
+            import_obj(
+                &dir.join("test.obj"),
+                &mut resources,
+                true,
+                BuildQuality::default(),
+            )
+            .map(|_| ())
+        };
+
+        let bumpy = import("newmtl red\nmap_Bump normals.png\nnewmtl blue\n");
+        assert!(bumpy.unwrap_err().contains("map_Bump"));
+        assert!(import("newmtl red\nNs 100\nmap_Ns shininess.png\nnewmtl blue\n").is_ok());
+
+        // The map holds the roughness of the full exponent.
+        let mut resources = Resources::default();
+        let mut importer = Importer {
+            resources: &mut resources,
+            base_dir: &dir,
+            build_quality: BuildQuality::default(),
+            materials: Vec::new(),
+            material_names: HashMap::new(),
+            material_keys: HashMap::new(),
+            image_keys: HashMap::new(),
+        };
+        let key = importer.shininess_texture("shininess.png", 100.).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let roughness = resources
+            .texture(key)
+            .sample(&resources, &TextureCoordinate::new(), &Position::new())
+            .x();
+        assert!((roughness - phong_roughness(100.)).abs() < 1. / 255.);
+    }
+}
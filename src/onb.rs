@@ -12,7 +12,7 @@ impl OrthoNormalBasis {
             Direction::from_values([1.0, 0.0, 0.0])
         };
 
-        let v = cross(w, &a);
+        let v = normalize(&cross(w, &a));
         let u = cross(w, &v);
         Self { axis: [u, v, *w] }
     }
@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use exr::prelude::*;
+use image::ImageFormat;
+
+use super::film::Film;
+use super::tone_mapping::ToneMapper;
+use super::vec::{XAccessor, YAccessor, ZAccessor};
+
+pub enum OutputFormat {
+    // Linear float formats, written straight from the film.
+    Exr,
+    Pfm,
+    // Tone mapped 8 bit formats supported by the image crate.
+    Ldr(ImageFormat),
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> std::result::Result<Self, String> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("exr") => Ok(Self::Exr),
+            Some("pfm") => Ok(Self::Pfm),
+            _ => match ImageFormat::from_path(path) {
+                Ok(format) if format.can_write() => Ok(Self::Ldr(format)),
+                _ => Err(format!("Unsupported output format {}", path.display())),
+            },
+        }
+    }
+
+    pub fn supports_aovs(&self) -> bool {
+        matches!(self, Self::Exr)
+    }
+}
+
+// Float formats store the film as is, the tone mapper is only used for 8 bit
+// formats.
+pub fn write_film(
+    film: &Film,
+    path: &Path,
+    tone_mapper: &dyn ToneMapper,
+) -> std::result::Result<(), String> {
+    let result = match OutputFormat::from_path(path)? {
+        OutputFormat::Exr => write_exr(film, path).map_err(|e| e.to_string()),
+        OutputFormat::Pfm => write_pfm(film, path).map_err(|e| e.to_string()),
+        OutputFormat::Ldr(format) if film.has_alpha() && supports_alpha(format) => film
+            .to_rgba_image(tone_mapper)
+            .save_with_format(path, format)
+            .map_err(|e| e.to_string()),
+        OutputFormat::Ldr(format) => film
+            .to_rgb_image(tone_mapper)
+            .save_with_format(path, format)
+            .map_err(|e| e.to_string()),
+    };
+
+    result.map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn supports_alpha(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Tga | ImageFormat::WebP
+    )
+}
+
+// Writes the film as a single part EXR. Auxiliary layers are stored as
+// `albedo.*`, `N.*` and `Z` channels next to the beauty pass.
+fn write_exr(film: &Film, path: &Path) -> exr::error::Result<()> {
+    let width = film.width();
+    let height = film.height();
+    let pixels: Vec<(u32, u32)> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .collect();
+
+    let channel = |name: &str, value: &dyn Fn(u32, u32) -> f32| {
+        AnyChannel::new(
+            name,
+            FlatSamples::F32(pixels.iter().map(|&(x, y)| value(x, y)).collect()),
+        )
+    };
+
+    let mut channels = vec![
+        channel("R", &|x, y| film.pixel(x, y).x()),
+        channel("G", &|x, y| film.pixel(x, y).y()),
+        channel("B", &|x, y| film.pixel(x, y).z()),
+    ];
+
+    if film.has_alpha() {
+        channels.push(channel("A", &|x, y| film.alpha(x, y)));
+    }
+
+    if film.has_aovs() {
+        let aovs = |x, y| *film.aovs(x, y).unwrap();
+        channels.extend([
+            channel("albedo.R", &|x, y| aovs(x, y).albedo.x()),
+            channel("albedo.G", &|x, y| aovs(x, y).albedo.y()),
+            channel("albedo.B", &|x, y| aovs(x, y).albedo.z()),
+            channel("N.X", &|x, y| aovs(x, y).normal.x()),
+            channel("N.Y", &|x, y| aovs(x, y).normal.y()),
+            channel("N.Z", &|x, y| aovs(x, y).normal.z()),
+            channel("Z", &|x, y| aovs(x, y).depth),
+        ]);
+    }
+
+    let layer = Layer::new(
+        (width as usize, height as usize),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    );
+
+    Image::from_layer(layer).write().to_file(path)
+}
+
+// Portable float map: a small text header followed by little endian RGB
+// floats, stored bottom row first.
+fn write_pfm(film: &Film, path: &Path) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "PF\n{} {}\n-1.0", film.width(), film.height())?;
+    for y in (0..film.height()).rev() {
+        for x in 0..film.width() {
+            let color = film.pixel(x, y);
+            for c in color.data {
+                writer.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::write_film;
+    use crate::film::Film;
+    use crate::tone_mapping::LinearToneMapper;
+    use crate::types::Color;
+
+    #[test]
+    fn test_write_pfm() {
+        let mut film = Film::new(2, 2);
+        film.add_samples(0, 0, &Color::from_values([1.0, 2.0, 3.0]), 1);
+        let path = std::env::temp_dir().join("toy_tracer_test_write_pfm.pfm");
+        write_film(&film, &path, &LinearToneMapper::new(0.0)).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+        assert_eq!(data.len(), header.len() + 2 * 2 * 3 * 4);
+
+        // The top left pixel is the first pixel of the last stored row.
+        let offset = header.len() + 2 * 3 * 4;
+        let red = f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        assert_eq!(red, 1.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+use std::path::Path;
+
+use super::acceleration_structure::BuildQuality;
+use super::hittable::TriangleMesh;
+use super::tone_mapping::srgb_eotf;
+use super::types::*;
+
+// Reads a PLY file in ASCII or binary encoding into a triangle mesh. Vertex
+// positions are required, normals, texture coordinates and colors are used
+// when present. Polygons are triangulated as fans.
+pub fn load_ply(path: &Path, build_quality: BuildQuality) -> Result<TriangleMesh, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+    parse_ply(&data, build_quality).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "char" | "int8" => Ok(Self::I8),
+            "uchar" | "uint8" => Ok(Self::U8),
+            "short" | "int16" => Ok(Self::I16),
+            "ushort" | "uint16" => Ok(Self::U16),
+            "int" | "int32" => Ok(Self::I32),
+            "uint" | "uint32" => Ok(Self::U32),
+            "float" | "float32" => Ok(Self::F32),
+            "double" | "float64" => Ok(Self::F64),
+            _ => Err(format!("Unknown property type '{}'", name)),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    ty: PropertyType,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|property| names.contains(&property.name.as_str()))
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<(Encoding, Vec<Element>, usize), String> {
+    if !data.starts_with(b"ply") {
+        return Err("Not a PLY file".to_string());
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let end = data[offset..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or("Missing end_header")?;
+        let line = String::from_utf8_lossy(&data[offset..offset + end]);
+        let line = line.trim();
+        offset += end + 1;
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["ply"] | [] => (),
+            ["comment", ..] | ["obj_info", ..] => (),
+            ["format", format, _] => {
+                encoding = Some(match format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(format!("Unknown format '{}'", format)),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("Invalid element count '{}'", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or("Property without element")?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    ty: PropertyType::List {
+                        count: ScalarType::parse(count)?,
+                        item: ScalarType::parse(item)?,
+                    },
+                }),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or("Property without element")?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    ty: PropertyType::Scalar(ScalarType::parse(ty)?),
+                }),
+            ["end_header"] => break,
+            _ => return Err(format!("Invalid header line '{}'", line)),
+        }
+    }
+
+    let encoding = encoding.ok_or("Missing format")?;
+    Ok((encoding, elements, offset))
+}
+
+// Reads the values of the body one at a time, whatever the encoding.
+struct Reader<'a> {
+    encoding: Encoding,
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        if self.encoding == Encoding::Ascii {
+            return self.read_ascii();
+        }
+
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + ty.size())
+            .ok_or("Unexpected end of file")?;
+        self.offset += ty.size();
+
+        let mut buffer = [0u8; 8];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        if self.encoding == Encoding::BinaryBigEndian {
+            buffer[..bytes.len()].reverse();
+        }
+
+        let value = match ty {
+            ScalarType::I8 => buffer[0] as i8 as f64,
+            ScalarType::U8 => buffer[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            ScalarType::U32 => u32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            ScalarType::F32 => f32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            ScalarType::F64 => f64::from_le_bytes(buffer),
+        };
+
+        Ok(value)
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, String> {
+        let rest = &self.data[self.offset..];
+        let start = rest
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .ok_or("Unexpected end of file")?;
+        let length = rest[start..]
+            .iter()
+            .position(|b| b.is_ascii_whitespace())
+            .unwrap_or(rest.len() - start);
+        self.offset += start + length;
+
+        let word = std::str::from_utf8(&rest[start..start + length]).unwrap_or("");
+        word.parse()
+            .map_err(|_| format!("Invalid value '{}'", word))
+    }
+
+    fn read_property(&mut self, ty: &PropertyType, values: &mut Vec<f64>) -> Result<(), String> {
+        values.clear();
+        match ty {
+            PropertyType::Scalar(ty) => values.push(self.read(*ty)?),
+            PropertyType::List { count, item } => {
+                let count = self.read(*count)? as usize;
+                for _ in 0..count {
+                    values.push(self.read(*item)?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_ply(data: &[u8], build_quality: BuildQuality) -> Result<TriangleMesh, String> {
+    let (encoding, elements, offset) = parse_header(data)?;
+    let mut reader = Reader {
+        encoding,
+        data,
+        offset,
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices = Vec::new();
+
+    let mut values = Vec::new();
+    let mut row = Vec::new();
+    for element in elements.iter() {
+        let vertex = |names: [&[&str]; 3]| names.map(|names| element.property(names));
+        let position = vertex([&["x"], &["y"], &["z"]]);
+        let normal = vertex([&["nx"], &["ny"], &["nz"]]);
+        let tex_coord = vertex([&["u", "s", "texture_u"], &["v", "t", "texture_v"], &[]]);
+        let color = vertex([&["red"], &["green"], &["blue"]]);
+        let face = element.property(&["vertex_indices", "vertex_index"]);
+
+        for _ in 0..element.count {
+            row.clear();
+            for property in element.properties.iter() {
+                reader.read_property(&property.ty, &mut values)?;
+                row.push(values.clone());
+            }
+
+            if element.name == "vertex" {
+                let get = |index: Option<usize>| index.map(|i| row[i][0] as f32);
+                match position.map(get) {
+                    [Some(x), Some(y), Some(z)] => positions.push(Position::from_values([x, y, z])),
+                    _ => return Err("Vertices without positions".to_string()),
+                }
+                if let [Some(x), Some(y), Some(z)] = normal.map(get) {
+                    normals.push(Normal::from_values([x, y, z]));
+                }
+                if let [Some(u), Some(v), _] = tex_coord.map(get) {
+                    tex_coords.push(TextureCoordinate::from_values([u, v]));
+                }
+                if let [Some(r), Some(g), Some(b)] = color.map(get) {
+                    // Integer colors are 8 bit sRGB, float colors are linear.
+                    let integer = color[0].is_some_and(|i| {
+                        !matches!(
+                            element.properties[i].ty,
+                            PropertyType::Scalar(ScalarType::F32 | ScalarType::F64)
+                        )
+                    });
+                    colors.push(Color::from_values(if integer {
+                        [r, g, b].map(|c| srgb_eotf(c / 255.))
+                    } else {
+                        [r, g, b]
+                    }));
+                }
+            } else if let (true, Some(face)) = (element.name == "face", face) {
+                let polygon = &row[face];
+                for i in 1..polygon.len().saturating_sub(1) {
+                    indices.extend([polygon[0], polygon[i], polygon[i + 1]].map(|i| i as u32));
+                }
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        return Err("No faces".to_string());
+    }
+    if indices.iter().any(|i| *i as usize >= positions.len()) {
+        return Err("Face index out of range".to_string());
+    }
+
+    let mesh = TriangleMesh::new_with_build_quality(
+        positions,
+        normals,
+        tex_coords,
+        indices,
+        build_quality,
+    );
+    if colors.is_empty() {
+        Ok(mesh)
+    } else {
+        Ok(mesh.with_colors(colors))
+    }
+}
+
+#[cfg(test)]
+mod ply_import_tests {
+    use super::parse_ply;
+    use crate::acceleration_structure::BuildQuality;
+    use crate::hittable::{Hittable, TriangleMesh};
+    use crate::intersection::Intersection;
+    use crate::ray::Ray;
+    use crate::types::*;
+    use crate::vec::*;
+
+    fn header(format: &str) -> String {
+        format!(
+            "ply\nformat {} 1.0\ncomment test\nelement vertex 4\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element face 1\nproperty list uchar int vertex_indices\nend_header\n",
+            format
+        )
+    }
+
+    fn check_quad(mesh: &TriangleMesh) {
+        let bounds = mesh.bounding_box().unwrap();
+        assert_eq!(bounds.max().x(), 1.);
+        assert_eq!(bounds.max().y(), 2.);
+
+        // The quad is split in two triangles, the first vertex is red.
+        let ray = Ray::new(&Position::new(), &Direction::from_values([0., 0., 1.]));
+        let first = Intersection::new(&ray, 1., 0, &Barycentrics::new());
+        let second = Intersection::new(&ray, 1., 3, &Barycentrics::new());
+        assert_eq!(mesh.vertex_color(&first).unwrap().x(), 1.);
+        assert_eq!(mesh.vertex_color(&second).unwrap().x(), 1.);
+    }
+
+    #[test]
+    fn test_ascii() {
+        let ply = header("ascii")
+            + "0 0 0 255 0 0\n1 0 0 0 255 0\n1 2 0 0 0 255\n0 2 0 0 0 0\n4 0 1 2 3\n";
+        check_quad(&parse_ply(ply.as_bytes(), BuildQuality::default()).unwrap());
+    }
+
+    #[test]
+    fn test_binary() {
+        let vertices = [[0., 0., 0.], [1., 0., 0.], [1., 2., 0.], [0., 2., 0.]];
+        for (format, big_endian) in [("binary_little_endian", false), ("binary_big_endian", true)] {
+            let mut ply = header(format).into_bytes();
+            for (i, vertex) in vertices.iter().enumerate() {
+                for c in vertex {
+                    let c: f32 = *c;
+                    ply.extend(if big_endian {
+                        c.to_be_bytes()
+                    } else {
+                        c.to_le_bytes()
+                    });
+                }
+                ply.extend([if i == 0 { 255 } else { 0 }, 0, 0]);
+            }
+            ply.push(4);
+            for i in 0..4i32 {
+                ply.extend(if big_endian {
+                    i.to_be_bytes()
+                } else {
+                    i.to_le_bytes()
+                });
+            }
+
+            check_quad(&parse_ply(&ply, BuildQuality::default()).unwrap());
+        }
+    }
+}
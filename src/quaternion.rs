@@ -0,0 +1,97 @@
+use super::types::*;
+use super::vec::*;
+
+// A rotation stored as a unit quaternion, `w` is the scalar part.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quaternion {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Quaternion {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self { x, y, z, w }
+    }
+
+    pub fn identity() -> Self {
+        Self::new(0., 0., 0., 1.)
+    }
+
+    // Counter clockwise rotation by `angle` radians about `axis`.
+    pub fn from_axis_angle(axis: &Direction, angle: f32) -> Self {
+        let axis = normalize(axis);
+        let (sin, cos) = (angle * 0.5).sin_cos();
+        Self::new(axis.x() * sin, axis.y() * sin, axis.z() * sin, cos)
+    }
+
+    pub fn length(&self) -> f32 {
+        (self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w).sqrt()
+    }
+
+    pub fn normalized(&self) -> Self {
+        let inv_length = 1. / self.length();
+        Self::new(
+            self.x * inv_length,
+            self.y * inv_length,
+            self.z * inv_length,
+            self.w * inv_length,
+        )
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    pub fn rotate(&self, v: &Vec3) -> Vec3 {
+        let u = Vec3::from_values([self.x, self.y, self.z]);
+        let t = cross(&u, v) * 2.;
+        *v + t * self.w + cross(&u, &t)
+    }
+
+    // The rotation as an affine transform, the quaternion is normalized first.
+    pub fn to_transform(&self) -> Transform {
+        let Self { x, y, z, w } = self.normalized();
+        let mut result = Transform::new();
+        result.colums[0] = Vec4::from_values([
+            1. - 2. * (y * y + z * z),
+            2. * (x * y - w * z),
+            2. * (x * z + w * y),
+            0.,
+        ]);
+        result.colums[1] = Vec4::from_values([
+            2. * (x * y + w * z),
+            1. - 2. * (x * x + z * z),
+            2. * (y * z - w * x),
+            0.,
+        ]);
+        result.colums[2] = Vec4::from_values([
+            2. * (x * z - w * y),
+            2. * (y * z + w * x),
+            1. - 2. * (x * x + y * y),
+            0.,
+        ]);
+        result
+    }
+}
+
+// Applies `rhs` first, then `self`.
+impl std::ops::Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, rhs: Quaternion) -> Self::Output {
+        Self::new(
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+        )
+    }
+}
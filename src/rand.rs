@@ -1,13 +1,23 @@
 use std::f32::consts::PI;
-use std::f32::consts::SQRT_2;
 
 use super::types::*;
 use super::vec::*;
+use rand::rngs::StdRng;
 use rand::Rng;
+use rand::SeedableRng;
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+// Reseeds the generator of the calling thread.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
 
 pub fn float() -> f32 {
-    let mut rng = rand::thread_rng();
-    rng.gen()
+    RNG.with(|rng| rng.borrow_mut().gen())
 }
 
 pub fn int_range(min: u32, max: u32) -> u32 {
@@ -46,8 +56,8 @@ pub fn cosine() -> Direction {
     let r2 = float();
     let z = (1.0 - r2).sqrt();
     let phi = 2.0 * PI * r1;
-    let x = phi.cos() * SQRT_2;
-    let y = phi.sin() * SQRT_2;
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
     Direction::from_values([x, y, z])
 }
 
@@ -31,4 +31,13 @@ impl Ray {
     pub fn at(&self, t: f32) -> Position {
         self.origin + self.dir * t
     }
+
+    // The direction is not normalized, so distances along the ray are the
+    // same before and after the transform.
+    pub fn transformed(&self, transform: &Transform) -> Ray {
+        Ray::new(
+            &transform.transform_point(&self.origin),
+            &transform.transform_vector(&self.dir),
+        )
+    }
 }
@@ -1,6 +1,8 @@
 use crate::camera::Camera;
+use crate::film::Film;
 use crate::light::Light;
 use crate::light::Lights;
+use crate::shader_binding_table::Payload;
 
 use super::acceleration_structure::*;
 use super::intersection::*;
@@ -8,17 +10,24 @@ use super::ray::*;
 use super::resources::Resources;
 use super::types::*;
 
+// The size of the image, the number of paths traced per pixel and the
+// number of rays along each path.
+#[derive(Clone, Copy)]
+pub struct TraceSettings {
+    pub width: u32,
+    pub height: u32,
+    pub spp: u32,
+    pub max_depth: u32,
+}
+
 pub trait RayTracer {
     fn trace(
         &self,
-        spp: u32,
-        max_depth: u32,
-        width: u32,
-        height: u32,
+        settings: &TraceSettings,
         scene: &TopLevelAccelerationStructure,
         lights: &Lights,
         resources: &Resources,
-    );
+    ) -> Film;
     fn intersect(
         &self,
         ray: &Ray,
@@ -27,29 +36,77 @@ pub trait RayTracer {
         t_min: f32,
         t_max: f32,
     ) -> Option<(u32, Intersection)>;
+
+    // Whether anything is hit between `t_min` and `t_max`, for shadow rays
+    // that don't need the closest hit.
+    fn occluded(
+        &self,
+        ray: &Ray,
+        scene: &TopLevelAccelerationStructure,
+        resources: &Resources,
+        t_min: f32,
+        t_max: f32,
+    ) -> bool;
+
+    // Traces a ray through the pipeline. The closest hit shader of the hit
+    // instance, or the miss shader at `miss_index`, updates the payload.
+    fn trace_ray(
+        &self,
+        context: &ShaderContext,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        miss_index: u32,
+        payload: &mut Payload,
+    );
+}
+
+// Everything a shader can access while a ray is traced.
+#[derive(Clone, Copy)]
+pub struct ShaderContext<'a> {
+    pub ray_tracer: &'a dyn RayTracer,
+    pub scene: &'a TopLevelAccelerationStructure,
+    pub lights: &'a Lights,
+    pub resources: &'a Resources,
 }
 
 pub trait RayGenerationShader {
+    // The color and coverage of pixel `x`, `y`.
     fn generate(
         &self,
-        ray_tracer: &dyn RayTracer,
-        scene: &TopLevelAccelerationStructure,
-        lights: &Lights,
-        resources: &Resources,
-        spp: u32,
-        max_depth: u32,
-        width: u32,
-        height: u32,
+        context: &ShaderContext,
+        settings: &TraceSettings,
         x: u32,
         y: u32,
-    ) -> Color;
+    ) -> (Color, f32);
+
+    // Camera ray through normalized image coordinates, used for the first hit
+    // auxiliary layers. Shaders without a camera return None.
+    fn primary_ray(&self, _u: f32, _v: f32) -> Option<Ray> {
+        None
+    }
 }
 
+// Shades the closest hit of an instance whose `hit_shader_id` selects it.
 pub trait ClosestHitShader {
     fn hit(
         &self,
-        scene: &TopLevelAccelerationStructure,
+        context: &ShaderContext,
+        instance_id: u32,
         intersection: &Intersection,
-        object_to_world: &Transform,
+        payload: &mut Payload,
     );
 }
+
+// Called for every candidate hit on an instance that has one, before the
+// closest hit is known. Returning false ignores the candidate, for example
+// for alpha cut outs.
+pub trait AnyHitShader {
+    fn any_hit(&self, resources: &Resources, instance_id: u32, intersection: &Intersection)
+        -> bool;
+}
+
+// Shades rays that leave the scene.
+pub trait MissShader {
+    fn miss(&self, context: &ShaderContext, ray: &Ray, payload: &mut Payload);
+}
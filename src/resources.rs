@@ -36,6 +36,10 @@ impl Resources {
         self.images.insert(image)
     }
 
+    pub fn image(&self, id: DefaultKey) -> &RgbaImage {
+        &self.images[id]
+    }
+
     pub fn material(&self, id: DefaultKey) -> &dyn Material {
         self.materials[id].as_ref()
     }
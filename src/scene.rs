@@ -1,14 +1,24 @@
 use slotmap::DefaultKey;
 
+use super::math_utils::degrees_to_radians;
+use super::quaternion::Quaternion;
 use super::types::*;
+use super::vec::*;
 
 #[derive(Copy, Clone)]
 pub struct Instance {
     pub geometry_index: DefaultKey,
     pub instance_id: u32,
     pub hit_shader_id: u32,
+    // Used for primitives that don't have a material of their own.
     pub material_id: DefaultKey,
+    // Change the transform through the builders, they keep the cached
+    // inverse in sync.
     pub transform: Transform,
+    pub world_to_object: Transform,
+    // The inverse transpose of the transform, which keeps normals
+    // perpendicular to surfaces under non-uniform scale.
+    pub normal_matrix: Transform,
     pub cull: bool,
 }
 
@@ -25,28 +35,110 @@ impl Instance {
             hit_shader_id: 0,
             material_id,
             transform: Transform::new(),
+            world_to_object: Transform::new(),
+            normal_matrix: Transform::new(),
             cull,
         }
     }
 
-    pub fn with_position(mut self, x: f32, y: f32, z: f32) -> Self {
-        self.transform.colums[0][3] = x;
-        self.transform.colums[1][3] = y;
-        self.transform.colums[2][3] = z;
+    // Selects the hit group of the shader binding table.
+    pub fn with_hit_shader(mut self, hit_shader_id: u32) -> Self {
+        self.hit_shader_id = hit_shader_id;
         self
     }
 
+    // Builders keep the transform in translation * rotation * scale order, so
+    // they can be called in any order. Rotations apply about the position of
+    // the instance, after earlier rotations.
+    pub fn with_position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.transform
+            .set_translation(&Vec3::from_values([x, y, z]));
+        self.updated()
+    }
+
     pub fn with_scale(mut self, x: f32, y: f32, z: f32) -> Self {
-        self.transform.colums[0][0] = x;
-        self.transform.colums[1][1] = y;
-        self.transform.colums[2][2] = z;
-        self
+        self.transform = self.transform * Transform::from_scale(&Vec3::from_values([x, y, z]));
+        self.updated()
+    }
+
+    pub fn with_uniform_scale(self, s: f32) -> Self {
+        self.with_scale(s, s, s)
+    }
+
+    // Rotates by `degrees` about `axis`.
+    pub fn with_rotation(self, axis: &Direction, degrees: f32) -> Self {
+        self.with_rotation_transform(&Transform::from_rotation(axis, degrees_to_radians(degrees)))
+    }
+
+    // Rotates about X, then Y, then Z by the given angles in degrees.
+    pub fn with_euler_angles(self, x: f32, y: f32, z: f32) -> Self {
+        self.with_rotation_transform(&Transform::from_euler_angles(
+            degrees_to_radians(x),
+            degrees_to_radians(y),
+            degrees_to_radians(z),
+        ))
+    }
+
+    pub fn with_quaternion(self, rotation: &Quaternion) -> Self {
+        self.with_rotation_transform(&rotation.to_transform())
     }
 
-    pub fn with_uniform_scale(mut self, s: f32) -> Self {
-        self.transform.colums[0][0] = s;
-        self.transform.colums[1][1] = s;
-        self.transform.colums[2][2] = s;
+    // Turns the instance so its local -Z axis points at `target`, replacing
+    // any earlier rotation.
+    pub fn with_look_at(mut self, target: &Position, up: &Direction) -> Self {
+        let position = self.transform.translation();
+        let m = &self.transform.colums;
+        let scale = Vec3::from_values(
+            [0, 1, 2].map(|c| length(&Vec3::from_values([m[0][c], m[1][c], m[2][c]]))),
+        );
+        self.transform = Transform::look_at(&position, target, up) * Transform::from_scale(&scale);
+        self.updated()
+    }
+
+    // Applies `transform` after the current transform.
+    pub fn with_transform(mut self, transform: &Transform) -> Self {
+        self.transform = *transform * self.transform;
+        self.updated()
+    }
+
+    // Like `with_transform`, but fails instead of panicking when the result
+    // can't be inverted, for transforms that come from user input.
+    pub fn try_with_transform(mut self, transform: &Transform) -> Result<Self, String> {
+        let transform = *transform * self.transform;
+        if transform.inverse().is_none() {
+            return Err("The transform can't be inverted".to_string());
+        }
+        self.transform = transform;
+        Ok(self.updated())
+    }
+
+    fn with_rotation_transform(mut self, rotation: &Transform) -> Self {
+        let translation = self.transform.translation();
+        self.transform.set_translation(&Vec3::new());
+        self.transform = *rotation * self.transform;
+        self.transform.set_translation(&translation);
+        self.updated()
+    }
+
+    pub fn normal_to_world(&self, normal: &Normal) -> Normal {
+        normalize(&self.normal_matrix.transform_vector(normal))
+    }
+
+    // Refreshes the cached inverse and normal matrix. A singular transform
+    // can't be intersected in object space, so it is rejected here rather
+    // than letting the instance silently vanish. `try_with_transform` checks
+    // for it up front.
+    fn updated(mut self) -> Self {
+        self.world_to_object = self
+            .transform
+            .inverse()
+            .expect("instance transform can't be inverted");
+        self.normal_matrix = Transform::new();
+        for row in 0..3 {
+            for column in 0..3 {
+                self.normal_matrix.colums[row][column] = self.world_to_object.colums[column][row];
+            }
+        }
         self
     }
 }
@@ -0,0 +1,901 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use slotmap::DefaultKey;
+
+use super::acceleration_structure::BuildQuality;
+use super::bounding_box::BoundingBox;
+use super::default_camera::DefaultCamera;
+use super::environment_map::EnvironmentMap;
+use super::gltf_import::import_gltf;
+use super::hittable::*;
+use super::light::{DirectionalLight, Lights, PointLight};
+use super::materials::*;
+use super::math_utils::degrees_to_radians;
+use super::miss_shaders::*;
+use super::obj_import::import_obj;
+use super::ply_import::load_ply;
+use super::quaternion::Quaternion;
+use super::raytracer::MissShader;
+use super::resources::Resources;
+use super::scene::Instance;
+use super::texture::*;
+use super::types::*;
+use super::vec::*;
+
+#[derive(Deserialize, Default)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub render: RenderSettings,
+    // Falls back to the first imported camera, or a camera framing the scene.
+    pub camera: Option<CameraDescription>,
+    // glTF, OBJ or PLY files whose meshes, materials, cameras and lights are
+    // added to the scene.
+    #[serde(default)]
+    pub imports: Vec<String>,
+    #[serde(default)]
+    pub textures: Vec<NamedTexture>,
+    #[serde(default)]
+    pub materials: Vec<NamedMaterial>,
+    #[serde(default)]
+    pub hittables: Vec<NamedHittable>,
+    #[serde(default)]
+    pub instances: Vec<InstanceDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub background: BackgroundDescription,
+}
+
+#[derive(Deserialize, Default)]
+pub struct BackgroundDescription {
+    #[serde(flatten)]
+    pub kind: BackgroundKind,
+    // Camera rays that miss write zero alpha, the background still lights
+    // the scene.
+    #[serde(default)]
+    pub transparent: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackgroundKind {
+    #[default]
+    Gradient,
+    Constant {
+        color: [f32; 3],
+    },
+    // An equirectangular texture, looked up by direction.
+    Environment {
+        texture: String,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+    // An equirectangular .hdr or .exr file that also lights the scene.
+    EnvironmentMap {
+        path: String,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        // Degrees around +Y.
+        #[serde(default)]
+        rotation: f32,
+    },
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub spp: u32,
+    pub max_depth: u32,
+    pub bvh: BuildQuality,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            spp: 1024,
+            max_depth: 32,
+            bvh: BuildQuality::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CameraDescription {
+    pub origin: [f32; 3],
+    pub look_at: [f32; 3],
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+    #[serde(default)]
+    pub aperture: f32,
+    pub focus_distance: Option<f32>,
+}
+
+fn default_fov() -> f32 {
+    45.
+}
+
+impl CameraDescription {
+    // Looks down -Z at the center of `bounds` from far enough away to see all of it.
+    pub fn framing(bounds: &BoundingBox) -> Self {
+        let fov = default_fov();
+        let center = bounds.center();
+        let radius = bounds.diagonal_length() * 0.5;
+        let distance = radius / (fov.to_radians() * 0.5).sin();
+        Self {
+            origin: (center + Position::from_values([0., 0., distance])).data,
+            look_at: center.data,
+            fov,
+            aperture: 0.,
+            focus_distance: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NamedTexture {
+    pub name: String,
+    #[serde(flatten)]
+    pub texture: TextureDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextureDescription {
+    Solid {
+        color: [f32; 3],
+    },
+    Checker {
+        even: String,
+        odd: String,
+        frequency: f32,
+    },
+    Image {
+        path: String,
+        // Color textures are usually stored in sRGB.
+        #[serde(default)]
+        srgb: bool,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct NamedMaterial {
+    pub name: String,
+    #[serde(flatten)]
+    pub material: MaterialDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDescription {
+    Diffuse {
+        albedo: String,
+    },
+    Mirror {
+        albedo: String,
+    },
+    Translucent {
+        albedo: String,
+        #[serde(default = "default_ior")]
+        ior: f32,
+    },
+    Pbr {
+        albedo: String,
+        roughness: String,
+        metal: String,
+        emission: String,
+        #[serde(default = "default_ior")]
+        ior: f32,
+        #[serde(default)]
+        transmission: f32,
+        #[serde(default = "default_fresnel_reflectance")]
+        fresnel_reflectance: f32,
+    },
+    // A metal from a preset, or from the real and imaginary parts of its index
+    // of refraction.
+    Conductor {
+        metal: Option<Metal>,
+        eta: Option<[f32; 3]>,
+        k: Option<[f32; 3]>,
+        roughness: String,
+        anisotropic: Option<String>,
+    },
+    // Disney's principled BSDF, the parameters left out take Disney's
+    // defaults.
+    Disney {
+        base_color: String,
+        metallic: Option<String>,
+        roughness: Option<String>,
+        anisotropic: Option<String>,
+        sheen: Option<String>,
+        sheen_tint: Option<String>,
+        clearcoat: Option<String>,
+        clearcoat_gloss: Option<String>,
+        specular_tint: Option<String>,
+        subsurface: Option<String>,
+        spec_trans: Option<String>,
+        emission: Option<String>,
+        #[serde(default = "default_ior")]
+        ior: f32,
+    },
+}
+
+fn default_ior() -> f32 {
+    1.5
+}
+
+fn default_fresnel_reflectance() -> f32 {
+    0.5
+}
+
+#[derive(Deserialize)]
+pub struct NamedHittable {
+    pub name: String,
+    #[serde(flatten)]
+    pub hittable: HittableDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HittableDescription {
+    Sphere {
+        radius: f32,
+        #[serde(default)]
+        position: [f32; 3],
+    },
+    // Every object in the file becomes a mesh, instances of the hittable
+    // instance all of them. Faces with an MTL material ignore the material of
+    // the instance unless `materials` is disabled.
+    Obj {
+        path: String,
+        #[serde(default = "default_true")]
+        materials: bool,
+    },
+    Ply {
+        path: String,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct InstanceDescription {
+    pub hittable: String,
+    pub material: String,
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: [f32; 3],
+    pub rotation: Option<RotationDescription>,
+    // Turns the instance so its -Z axis faces this point, with Y up.
+    pub look_at: Option<[f32; 3]>,
+    #[serde(default)]
+    pub cull: bool,
+}
+
+impl InstanceDescription {
+    // Translation * rotation * scale, the same order the instance builders
+    // keep. A look at target replaces the rotation.
+    fn transform(&self) -> Transform {
+        let position = Position::from_values(self.position);
+        let scale = Transform::from_scale(&Vec3::from_values(self.scale));
+        if let Some(target) = self.look_at {
+            let up = Direction::from_values([0., 1., 0.]);
+            return Transform::look_at(&position, &Position::from_values(target), &up) * scale;
+        }
+
+        let rotation = match self.rotation {
+            Some(RotationDescription::AxisAngle { axis, angle }) => {
+                Transform::from_rotation(&Direction::from_values(axis), degrees_to_radians(angle))
+            }
+            Some(RotationDescription::Euler { euler: [x, y, z] }) => Transform::from_euler_angles(
+                degrees_to_radians(x),
+                degrees_to_radians(y),
+                degrees_to_radians(z),
+            ),
+            Some(RotationDescription::Quaternion {
+                quaternion: [x, y, z, w],
+            }) => Quaternion::new(x, y, z, w).to_transform(),
+            None => Transform::new(),
+        };
+        Transform::from_translation(&position) * rotation * scale
+    }
+}
+
+// Angles are in degrees.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RotationDescription {
+    AxisAngle { axis: [f32; 3], angle: f32 },
+    Euler { euler: [f32; 3] },
+    Quaternion { quaternion: [f32; 4] },
+}
+
+fn default_scale() -> [f32; 3] {
+    [1., 1., 1.]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LightDescription {
+    Directional {
+        direction: [f32; 3],
+        #[serde(default = "default_light_color")]
+        color: [f32; 3],
+        #[serde(default = "default_light_intensity")]
+        intensity: f32,
+    },
+    Point {
+        position: [f32; 3],
+        #[serde(default = "default_light_color")]
+        color: [f32; 3],
+        #[serde(default = "default_light_intensity")]
+        intensity: f32,
+    },
+}
+
+fn default_light_color() -> [f32; 3] {
+    [1., 1., 1.]
+}
+
+fn default_light_intensity() -> f32 {
+    10.
+}
+
+pub struct LoadedScene {
+    pub resources: Resources,
+    pub instances: Vec<Instance>,
+    pub lights: Lights,
+    pub camera: DefaultCamera,
+    pub settings: RenderSettings,
+    // The miss shader for rays that leave the scene.
+    pub background: Box<dyn MissShader>,
+    pub transparent: bool,
+}
+
+impl SceneDescription {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid scene description: {}", e))
+    }
+
+    // Reads a JSON scene description. Model files are wrapped in a description
+    // that only imports them.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let extension = path.extension().and_then(|e| e.to_str());
+        if let Some("gltf" | "glb" | "obj" | "ply") = extension {
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| format!("Invalid path {}", path.display()))?;
+            return Ok(Self {
+                imports: vec![file_name.to_string()],
+                ..Default::default()
+            });
+        }
+
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read scene {}: {}", path.display(), e))?;
+        Self::from_json(&json)
+    }
+
+    /// Builds the scene. Relative asset paths are resolved against `base_dir`.
+    pub fn build(&self, base_dir: &Path) -> Result<LoadedScene, String> {
+        let mut resources = Resources::default();
+
+        let mut textures = HashMap::new();
+        for named in self.textures.iter() {
+            let key = match &named.texture {
+                TextureDescription::Solid { color } => {
+                    resources.add_texture(SolidColorTexture::new(&Color::from_values(*color)))
+                }
+                TextureDescription::Checker {
+                    even,
+                    odd,
+                    frequency,
+                } => resources.add_texture(CheckerTexture::new(
+                    lookup(&textures, "texture", even)?,
+                    lookup(&textures, "texture", odd)?,
+                    *frequency,
+                )),
+                TextureDescription::Image { path, srgb } => {
+                    let path = base_dir.join(path);
+                    let image = image::open(&path)
+                        .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+                    let texture = ImageTexture::new(resources.add_image(image.to_rgba8()));
+                    if *srgb {
+                        resources.add_texture(texture.with_srgb())
+                    } else {
+                        resources.add_texture(texture)
+                    }
+                }
+            };
+            textures.insert(named.name.clone(), key);
+        }
+
+        let mut materials = HashMap::new();
+        for named in self.materials.iter() {
+            let key = match &named.material {
+                MaterialDescription::Diffuse { albedo } => resources
+                    .add_material(DiffuseMaterial::new(lookup(&textures, "texture", albedo)?)),
+                MaterialDescription::Mirror { albedo } => resources
+                    .add_material(MirrorMaterial::new(lookup(&textures, "texture", albedo)?)),
+                MaterialDescription::Translucent { albedo, ior } => resources.add_material(
+                    TranslucentMaterial::new(lookup(&textures, "texture", albedo)?, *ior),
+                ),
+                MaterialDescription::Pbr {
+                    albedo,
+                    roughness,
+                    metal,
+                    emission,
+                    ior,
+                    transmission,
+                    fresnel_reflectance,
+                } => resources.add_material(PBRMaterial::new(
+                    lookup(&textures, "texture", albedo)?,
+                    lookup(&textures, "texture", roughness)?,
+                    lookup(&textures, "texture", metal)?,
+                    lookup(&textures, "texture", emission)?,
+                    *ior,
+                    *transmission,
+                    *fresnel_reflectance,
+                )),
+                MaterialDescription::Conductor {
+                    metal,
+                    eta,
+                    k,
+                    roughness,
+                    anisotropic,
+                } => {
+                    let roughness = lookup(&textures, "texture", roughness)?;
+                    let mut material = match (metal, eta, k) {
+                        (Some(metal), None, None) => {
+                            ConductorMaterial::from_metal(*metal, roughness)
+                        }
+                        (None, Some(eta), Some(k)) => ConductorMaterial::new(
+                            &Color::from_values(*eta),
+                            &Color::from_values(*k),
+                            roughness,
+                        ),
+                        _ => {
+                            return Err(format!(
+                                "Conductor '{}' needs either a metal or both eta and k",
+                                named.name
+                            ))
+                        }
+                    };
+                    if let Some(anisotropic) = anisotropic {
+                        material =
+                            material.with_anisotropic(lookup(&textures, "texture", anisotropic)?);
+                    }
+                    resources.add_material(material)
+                }
+                MaterialDescription::Disney {
+                    base_color,
+                    metallic,
+                    roughness,
+                    anisotropic,
+                    sheen,
+                    sheen_tint,
+                    clearcoat,
+                    clearcoat_gloss,
+                    specular_tint,
+                    subsurface,
+                    spec_trans,
+                    emission,
+                    ior,
+                } => {
+                    let optional = |name: &Option<String>| {
+                        name.as_deref()
+                            .map(|name| lookup(&textures, "texture", name))
+                            .transpose()
+                    };
+                    let mut material =
+                        DisneyMaterial::new(lookup(&textures, "texture", base_color)?)
+                            .with_ior(*ior);
+                    material.metallic = optional(metallic)?;
+                    material.roughness = optional(roughness)?;
+                    material.anisotropic = optional(anisotropic)?;
+                    material.sheen = optional(sheen)?;
+                    material.sheen_tint = optional(sheen_tint)?;
+                    material.clearcoat = optional(clearcoat)?;
+                    material.clearcoat_gloss = optional(clearcoat_gloss)?;
+                    material.specular_tint = optional(specular_tint)?;
+                    material.subsurface = optional(subsurface)?;
+                    material.spec_trans = optional(spec_trans)?;
+                    material.emission = optional(emission)?;
+                    resources.add_material(material)
+                }
+            };
+            materials.insert(named.name.clone(), key);
+        }
+
+        let mut hittables = HashMap::new();
+        for named in self.hittables.iter() {
+            let keys = match &named.hittable {
+                HittableDescription::Sphere { radius, position } => {
+                    let sphere = Sphere::new(*radius, &Position::from_values(*position));
+                    vec![resources.add_hittable(sphere)]
+                }
+                HittableDescription::Obj { path, materials } => import_obj(
+                    &base_dir.join(path),
+                    &mut resources,
+                    *materials,
+                    self.render.bvh,
+                )?,
+                HittableDescription::Ply { path } => {
+                    vec![resources.add_hittable(load_ply(&base_dir.join(path), self.render.bvh)?)]
+                }
+            };
+            hittables.insert(named.name.clone(), keys);
+        }
+
+        let mut instances = Vec::new();
+        for description in self.instances.iter() {
+            let transform = description.transform();
+            let geometry = hittables
+                .get(&description.hittable)
+                .ok_or_else(|| format!("Unknown hittable '{}'", description.hittable))?;
+            let material = lookup(&materials, "material", &description.material)?;
+            for key in geometry.iter() {
+                let instance =
+                    Instance::new(*key, instances.len() as u32, material, description.cull)
+                        .try_with_transform(&transform)
+                        .map_err(|e| format!("Instance of '{}': {}", description.hittable, e))?;
+                instances.push(instance);
+            }
+        }
+
+        let mut lights = Lights::new();
+        for light in self.lights.iter() {
+            match light {
+                LightDescription::Directional {
+                    direction,
+                    color,
+                    intensity,
+                } => lights.add(
+                    DirectionalLight::new(Direction::from_values(*direction))
+                        .with_color(Color::from_values(*color))
+                        .with_intensity(*intensity),
+                ),
+                LightDescription::Point {
+                    position,
+                    color,
+                    intensity,
+                } => lights.add(
+                    PointLight::new(Position::from_values(*position))
+                        .with_color(Color::from_values(*color))
+                        .with_intensity(*intensity),
+                ),
+            }
+        }
+
+        let mut cameras = Vec::new();
+        let mut default_material = None;
+        for path in self.imports.iter() {
+            let path = base_dir.join(path);
+            let meshes = match path.extension().and_then(|e| e.to_str()) {
+                Some("obj") => import_obj(&path, &mut resources, true, self.render.bvh)?,
+                Some("ply") => vec![resources.add_hittable(load_ply(&path, self.render.bvh)?)],
+                _ => {
+                    cameras.extend(import_gltf(
+                        &path,
+                        &mut resources,
+                        &mut instances,
+                        &mut lights,
+                        self.render.bvh,
+                    )?);
+                    continue;
+                }
+            };
+
+            let material = *default_material.get_or_insert_with(|| {
+                let albedo = resources.add_texture(SolidColorTexture::new(&Color::splat(0.8)));
+                resources.add_material(DiffuseMaterial::new(albedo))
+            });
+            for key in meshes {
+                instances.push(Instance::new(key, instances.len() as u32, material, false));
+            }
+        }
+
+        lights.add_emitters(&resources, &instances);
+
+        let camera = match self.camera.as_ref().or(cameras.first()) {
+            Some(camera) => camera.clone(),
+            None => CameraDescription::framing(&scene_bounds(&resources, &instances)?),
+        };
+        let origin = Position::from_values(camera.origin);
+        let look_at = Position::from_values(camera.look_at);
+        let camera = DefaultCamera::new(
+            &origin,
+            &look_at,
+            self.render.width as f32 / self.render.height as f32,
+            camera.fov,
+            camera.aperture,
+            camera
+                .focus_distance
+                .unwrap_or_else(|| distance(&look_at, &origin)),
+        );
+
+        let background: Box<dyn MissShader> = match &self.background.kind {
+            BackgroundKind::Gradient => with_transparency(GradientMissShader {}, &self.background),
+            BackgroundKind::Constant { color } => with_transparency(
+                ConstantMissShader::new(&Color::from_values(*color)),
+                &self.background,
+            ),
+            BackgroundKind::Environment { texture, intensity } => with_transparency(
+                EnvironmentMissShader::new(lookup(&textures, "texture", texture)?)
+                    .with_intensity(*intensity),
+                &self.background,
+            ),
+            BackgroundKind::EnvironmentMap {
+                path,
+                intensity,
+                rotation,
+            } => {
+                let map = EnvironmentMap::open(&base_dir.join(path))?
+                    .with_intensity(*intensity)
+                    .with_rotation(*rotation);
+                let map = Arc::new(map);
+                lights.set_environment(map.clone());
+                with_transparency(EnvironmentMapMissShader::new(map), &self.background)
+            }
+        };
+
+        Ok(LoadedScene {
+            resources,
+            instances,
+            lights,
+            camera,
+            settings: self.render.clone(),
+            background,
+            transparent: self.background.transparent,
+        })
+    }
+}
+
+fn scene_bounds(resources: &Resources, instances: &[Instance]) -> Result<BoundingBox, String> {
+    instances
+        .iter()
+        .filter_map(|instance| {
+            resources
+                .hittable(instance.geometry_index)
+                .bounding_box()
+                .map(|bb| bb.transformed(&instance.transform))
+        })
+        .reduce(|a, b| BoundingBox::surrounding_box(&a, &b))
+        .ok_or_else(|| "Scene has no camera and nothing to frame".to_string())
+}
+
+fn with_transparency<M>(background: M, description: &BackgroundDescription) -> Box<dyn MissShader>
+where
+    M: MissShader + 'static,
+{
+    if description.transparent {
+        Box::new(TransparentMissShader::new(background))
+    } else {
+        Box::new(background)
+    }
+}
+
+fn lookup(
+    keys: &HashMap<String, DefaultKey>,
+    kind: &str,
+    name: &str,
+) -> Result<DefaultKey, String> {
+    keys.get(name)
+        .copied()
+        .ok_or_else(|| format!("Unknown {} '{}'", kind, name))
+}
+
+#[cfg(test)]
+mod scene_description_tests {
+    use std::path::Path;
+
+    use image::{Rgb, Rgb32FImage};
+
+    use super::SceneDescription;
+    use crate::types::Direction;
+    use crate::vec::*;
+
+    const SCENE: &str = r#"{
+        "render": { "width": 64, "height": 32, "spp": 4 },
+        "camera": { "origin": [0, 0, 5], "look_at": [0, 0, 0] },
+        "textures": [
+            { "name": "white", "type": "solid", "color": [1, 1, 1] },
+            { "name": "black", "type": "solid", "color": [0, 0, 0] },
+            { "name": "checker", "type": "checker", "even": "white", "odd": "black", "frequency": 3 }
+        ],
+        "materials": [
+            { "name": "floor", "type": "diffuse", "albedo": "checker" }
+        ],
+        "hittables": [
+            { "name": "ball", "type": "sphere", "radius": 1 }
+        ],
+        "instances": [
+            { "hittable": "ball", "material": "floor", "position": [0, -100, 0], "scale": [100, 100, 100] },
+            { "hittable": "ball", "material": "floor" }
+        ],
+        "lights": [
+            { "type": "directional", "direction": [-1, 1, 1] }
+        ]
+    }"#;
+
+    #[test]
+    fn test_build_scene() {
+        let scene = SceneDescription::from_json(SCENE)
+            .unwrap()
+            .build(Path::new("."))
+            .unwrap();
+        assert_eq!(scene.settings.width, 64);
+        assert_eq!(scene.settings.max_depth, 32);
+        assert_eq!(scene.instances.len(), 2);
+        assert_eq!(scene.instances[1].instance_id, 1);
+        assert_eq!(scene.instances[0].transform.colums[1][3], -100.);
+        assert_eq!(scene.lights.data().len(), 1);
+    }
+
+    #[test]
+    fn test_rotated_instance() {
+        let scene = SCENE.replace(
+            r#"{ "hittable": "ball", "material": "floor" }"#,
+            r#"{ "hittable": "ball", "material": "floor", "scale": [2, 1, 1],
+                 "rotation": { "axis": [0, 0, 1], "angle": 90 } }"#,
+        );
+        let scene = SceneDescription::from_json(&scene)
+            .unwrap()
+            .build(Path::new("."))
+            .unwrap();
+
+        // The scale is applied before the rotation, so local X ends up along Y.
+        let transform = &scene.instances[1].transform;
+        assert!(transform.colums[0][0].abs() < 0.0001);
+        assert!((transform.colums[1][0] - 2.).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_instance_transforms() {
+        let build = |instance: &str| {
+            let scene = SCENE.replace(r#"{ "hittable": "ball", "material": "floor" }"#, instance);
+            SceneDescription::from_json(&scene)
+                .unwrap()
+                .build(Path::new("."))
+        };
+
+        // Looking straight up along the default up axis.
+        let scene = build(
+            r#"{ "hittable": "ball", "material": "floor", "position": [0, 0, 0], "look_at": [0, 5, 0] }"#,
+        )
+        .unwrap();
+        assert!((scene.instances[1].transform.colums[1][2] + 1.).abs() < 0.0001);
+
+        for scale in ["[0, 1, 1]", "[1e-20, 1e-20, 1]"] {
+            let result = build(&format!(
+                r#"{{ "hittable": "ball", "material": "floor", "scale": {} }}"#,
+                scale
+            ));
+            assert_eq!(
+                result.err().unwrap(),
+                "Instance of 'ball': The transform can't be inverted"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_reference() {
+        let scene = SCENE.replace(r#""albedo": "checker""#, r#""albedo": "missing""#);
+        let result = SceneDescription::from_json(&scene)
+            .unwrap()
+            .build(Path::new("."));
+        assert_eq!(result.err().unwrap(), "Unknown texture 'missing'");
+    }
+
+    #[test]
+    fn test_background() {
+        let scene = SCENE.replace(
+            r#""lights": ["#,
+            r#""background": { "type": "environment", "texture": "white", "transparent": true },
+               "lights": ["#,
+        );
+        let scene = SceneDescription::from_json(&scene)
+            .unwrap()
+            .build(Path::new("."))
+            .unwrap();
+        assert!(scene.transparent);
+
+        let scene = SCENE.replace(
+            r#""lights": ["#,
+            r#""background": { "type": "environment", "texture": "missing" }, "lights": ["#,
+        );
+        let result = SceneDescription::from_json(&scene)
+            .unwrap()
+            .build(Path::new("."));
+        assert_eq!(result.err().unwrap(), "Unknown texture 'missing'");
+    }
+
+    #[test]
+    fn test_conductor() {
+        let conductor = |material: &str| {
+            let scene = SCENE.replace(
+                r#""materials": ["#,
+                &format!(
+                    r#""materials": [ {{ "name": "metal", "type": "conductor", {} }},"#,
+                    material
+                ),
+            );
+            SceneDescription::from_json(&scene)
+                .unwrap()
+                .build(Path::new("."))
+        };
+
+        assert!(conductor(r#""metal": "chromium", "roughness": "white""#).is_ok());
+        assert!(conductor(
+            r#""eta": [0.2, 0.9, 1.1], "k": [3.9, 2.5, 2.1], "roughness": "white", "anisotropic": "white""#
+        )
+        .is_ok());
+        assert!(conductor(r#""eta": [0.2, 0.9, 1.1], "roughness": "white""#).is_err());
+        assert!(conductor(r#""metal": "gold", "roughness": "missing""#).is_err());
+    }
+
+    #[test]
+    fn test_emitters() {
+        let scene = SCENE
+            .replace(
+                r#""materials": ["#,
+                r#""materials": [
+                   { "name": "lamp", "type": "pbr", "albedo": "white", "roughness": "white",
+                     "metal": "black", "emission": "white" },"#,
+            )
+            .replace(
+                r#"{ "hittable": "ball", "material": "floor" }"#,
+                r#"{ "hittable": "ball", "material": "floor" },
+                   { "hittable": "ball", "material": "lamp", "position": [0, 3, 0] }"#,
+            );
+        let scene = SceneDescription::from_json(&scene)
+            .unwrap()
+            .build(Path::new("."))
+            .unwrap();
+        assert_eq!(scene.lights.emitters(), &[2]);
+        assert!(!scene.lights.is_emitter(1));
+    }
+
+    #[test]
+    fn test_environment_map() {
+        let dir = std::env::temp_dir();
+        Rgb32FImage::from_pixel(8, 4, Rgb([2., 1., 0.5]))
+            .save(dir.join("toy_tracer_test_environment_map.exr"))
+            .unwrap();
+        let scene = SCENE.replace(
+            r#""lights": ["#,
+            r#""background": { "type": "environment_map",
+                              "path": "toy_tracer_test_environment_map.exr",
+                              "intensity": 2, "rotation": 90 },
+               "lights": ["#,
+        );
+        let scene = SceneDescription::from_json(&scene)
+            .unwrap()
+            .build(&dir)
+            .unwrap();
+        let environment = scene.lights.environment().unwrap();
+        let radiance = environment.radiance(&Direction::from_values([0., 1., 0.]));
+        assert!((radiance.x() - 4.).abs() < 0.0001);
+
+        let scene = SCENE.replace(
+            r#""lights": ["#,
+            r#""background": { "type": "environment_map", "path": "missing.hdr" }, "lights": ["#,
+        );
+        let result = SceneDescription::from_json(&scene).unwrap().build(&dir);
+        assert!(result.is_err());
+    }
+}
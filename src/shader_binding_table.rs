@@ -0,0 +1,132 @@
+use super::hit_shaders::PathTracingHitShader;
+use super::miss_shaders::GradientMissShader;
+use super::ray::Ray;
+use super::raytracer::{AnyHitShader, ClosestHitShader, MissShader};
+use super::types::*;
+
+// Passed from the ray generation shader to the hit and miss shaders of every
+// ray along a path.
+pub struct Payload {
+    // Radiance gathered along the path so far.
+    pub radiance: Color,
+    // Weight of the radiance the next ray brings back.
+    pub throughput: Color,
+    // Set by a hit shader to continue the path.
+    pub next_ray: Option<Ray>,
+    // Number of rays traced so far.
+    pub depth: u32,
+    // Coverage of the pixel, a transparent background clears it.
+    pub alpha: f32,
+    // Solid angle density the last hit sampled `next_ray` with, zero for
+    // camera rays and delta lobes.
+    pub pdf: f32,
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self {
+            radiance: Color::new(),
+            throughput: Color::ones(),
+            next_ray: None,
+            depth: 0,
+            alpha: 1.,
+            pdf: 0.,
+        }
+    }
+}
+
+pub struct HitGroup {
+    pub closest_hit: Box<dyn ClosestHitShader>,
+    pub any_hit: Option<Box<dyn AnyHitShader>>,
+}
+
+// Holds the hit groups that instances select with `hit_shader_id` and the
+// miss shaders that `RayTracer::trace_ray` selects by index. The default
+// table has the path tracing hit shader and the gradient sky at index 0.
+// Ids without a shader fall back to index 0, which `validate` checks for.
+pub struct ShaderBindingTable {
+    hit_groups: Vec<HitGroup>,
+    miss_shaders: Vec<Box<dyn MissShader>>,
+}
+
+impl Default for ShaderBindingTable {
+    fn default() -> Self {
+        Self::with_background(Box::new(GradientMissShader {}))
+    }
+}
+
+impl ShaderBindingTable {
+    pub fn new() -> Self {
+        Self {
+            hit_groups: Vec::new(),
+            miss_shaders: Vec::new(),
+        }
+    }
+
+    // The path tracing hit shader with `background` as miss shader 0.
+    pub fn with_background(background: Box<dyn MissShader>) -> Self {
+        let mut table = Self::new();
+        table.add_hit_group(PathTracingHitShader {});
+        table.miss_shaders.push(background);
+        table
+    }
+
+    pub fn add_hit_group<C>(&mut self, closest_hit: C) -> u32
+    where
+        C: ClosestHitShader + 'static,
+    {
+        self.hit_groups.push(HitGroup {
+            closest_hit: Box::new(closest_hit),
+            any_hit: None,
+        });
+        self.hit_groups.len() as u32 - 1
+    }
+
+    pub fn add_hit_group_with_any_hit<C, A>(&mut self, closest_hit: C, any_hit: A) -> u32
+    where
+        C: ClosestHitShader + 'static,
+        A: AnyHitShader + 'static,
+    {
+        self.hit_groups.push(HitGroup {
+            closest_hit: Box::new(closest_hit),
+            any_hit: Some(Box::new(any_hit)),
+        });
+        self.hit_groups.len() as u32 - 1
+    }
+
+    pub fn add_miss_shader<M>(&mut self, miss: M) -> u32
+    where
+        M: MissShader + 'static,
+    {
+        self.miss_shaders.push(Box::new(miss));
+        self.miss_shaders.len() as u32 - 1
+    }
+
+    // A table needs hit group 0 and miss shader 0 to have something to fall
+    // back to.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.hit_groups.is_empty() {
+            return Err("The shader binding table has no hit groups".to_string());
+        }
+        if self.miss_shaders.is_empty() {
+            return Err("The shader binding table has no miss shaders".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn hit_group(&self, id: u32) -> &HitGroup {
+        self.hit_groups
+            .get(id as usize)
+            .unwrap_or(&self.hit_groups[0])
+    }
+
+    pub fn miss_shader(&self, index: u32) -> &dyn MissShader {
+        self.miss_shaders
+            .get(index as usize)
+            .unwrap_or(&self.miss_shaders[0])
+            .as_ref()
+    }
+}
+
+unsafe impl Send for ShaderBindingTable {}
+unsafe impl Sync for ShaderBindingTable {}
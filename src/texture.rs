@@ -1,11 +1,18 @@
+use image::RgbaImage;
 use slotmap::DefaultKey;
 
 use super::resources::Resources;
+use super::tone_mapping::srgb_eotf;
 use super::types::*;
 use super::vec::*;
 pub trait Texture {
     fn uid(&self) -> usize;
     fn sample(&self, resources: &Resources, uv: &TextureCoordinate, position: &Position) -> Color;
+
+    // True when every sample is black, textures that can't tell return false.
+    fn is_black(&self) -> bool {
+        false
+    }
 }
 
 pub struct SolidColorTexture {
@@ -26,6 +33,10 @@ impl Texture for SolidColorTexture {
     fn uid(&self) -> usize {
         1
     }
+
+    fn is_black(&self) -> bool {
+        self.color.data.iter().all(|c| *c == 0.)
+    }
 }
 
 pub struct CheckerTexture {
@@ -61,8 +72,61 @@ impl Texture for CheckerTexture {
     }
 }
 
+// Bilinearly filtered image lookup with repeat wrapping. The v coordinate
+// points up, so v = 0 is the bottom row of the image.
 pub struct ImageTexture {
-    image_id: usize,
+    image: DefaultKey,
+    factor: Color,
+    channel: Option<usize>,
+    srgb: bool,
+}
+
+impl ImageTexture {
+    pub fn new(image: DefaultKey) -> Self {
+        Self {
+            image,
+            factor: Color::ones(),
+            channel: None,
+            srgb: false,
+        }
+    }
+
+    // Decode texels from sRGB to linear, used for color data.
+    pub fn with_srgb(mut self) -> Self {
+        self.srgb = true;
+        self
+    }
+
+    // Multiplies every sample by `factor`.
+    pub fn with_factor(mut self, factor: &Color) -> Self {
+        self.factor = *factor;
+        self
+    }
+
+    // Returns a single channel of the image, splatted to all components.
+    pub fn with_channel(mut self, channel: usize) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    fn texel(&self, image: &RgbaImage, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(image.width() as i64) as u32;
+        let y = y.rem_euclid(image.height() as i64) as u32;
+        let pixel = image.get_pixel(x, y);
+        let decode = |c: u8| {
+            let c = c as f32 / 255.;
+            if self.srgb {
+                srgb_eotf(c)
+            } else {
+                c
+            }
+        };
+
+        match self.channel {
+            Some(channel) => Color::splat(decode(pixel[channel])),
+            None => Color::from_values([decode(pixel[0]), decode(pixel[1]), decode(pixel[2])]),
+        }
+    }
 }
 
 impl Texture for ImageTexture {
@@ -70,7 +134,23 @@ impl Texture for ImageTexture {
         3
     }
 
-    fn sample(&self, resources: &Resources, uv: &TextureCoordinate, position: &Position) -> Color {
-        Color::new()
+    fn is_black(&self) -> bool {
+        self.factor.data.iter().all(|c| *c == 0.)
+    }
+
+    fn sample(&self, resources: &Resources, uv: &TextureCoordinate, _: &Position) -> Color {
+        let image = resources.image(self.image);
+        let x = uv.x() * image.width() as f32 - 0.5;
+        let y = (1. - uv.y()) * image.height() as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(image, x0, y0) * (1. - tx) + self.texel(image, x0 + 1, y0) * tx;
+        let bottom =
+            self.texel(image, x0, y0 + 1) * (1. - tx) + self.texel(image, x0 + 1, y0 + 1) * tx;
+        (top * (1. - ty) + bottom * ty) * self.factor
     }
 }
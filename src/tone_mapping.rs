@@ -0,0 +1,228 @@
+use super::math_utils::Saturate;
+use super::types::*;
+use super::vec::*;
+
+pub fn luminance(color: &Color) -> f32 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+pub fn srgb_oetf(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+// The inverse of `srgb_oetf`, decodes sRGB encoded values to linear.
+pub fn srgb_eotf(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Maps linear scene radiance to linear display values in [0, 1]. The
+// exposure, in stops, is applied before the curve.
+pub trait ToneMapper {
+    fn exposure(&self) -> f32;
+    fn map(&self, color: &Color) -> Color;
+
+    fn display(&self, color: &Color) -> [u8; 3] {
+        let exposed = *color * 2f32.powf(self.exposure());
+        let mapped = self.map(&exposed);
+        mapped
+            .data
+            .map(|c| (srgb_oetf(c.clamp(0., 1.)) * 255. + 0.5) as u8)
+    }
+}
+
+pub struct LinearToneMapper {
+    exposure: f32,
+}
+
+impl LinearToneMapper {
+    pub fn new(exposure: f32) -> Self {
+        Self { exposure }
+    }
+}
+
+impl ToneMapper for LinearToneMapper {
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    fn map(&self, color: &Color) -> Color {
+        color.saturate()
+    }
+}
+
+pub struct ReinhardToneMapper {
+    exposure: f32,
+}
+
+impl ReinhardToneMapper {
+    pub fn new(exposure: f32) -> Self {
+        Self { exposure }
+    }
+}
+
+impl ToneMapper for ReinhardToneMapper {
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    fn map(&self, color: &Color) -> Color {
+        *color / (Color::ones() + color)
+    }
+}
+
+// Reinhard applied to luminance only, which keeps saturated highlights from
+// shifting hue.
+pub struct LuminanceReinhardToneMapper {
+    exposure: f32,
+}
+
+impl LuminanceReinhardToneMapper {
+    pub fn new(exposure: f32) -> Self {
+        Self { exposure }
+    }
+}
+
+impl ToneMapper for LuminanceReinhardToneMapper {
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    fn map(&self, color: &Color) -> Color {
+        let l = luminance(color);
+        if l <= 0. {
+            return Color::new();
+        }
+
+        *color * (1. / (1. + l))
+    }
+}
+
+// Luminance Reinhard that maps `white_point` and everything above it to white.
+pub struct ExtendedReinhardToneMapper {
+    exposure: f32,
+    white_point: f32,
+}
+
+impl ExtendedReinhardToneMapper {
+    pub fn new(exposure: f32, white_point: f32) -> Self {
+        Self {
+            exposure,
+            white_point,
+        }
+    }
+}
+
+impl ToneMapper for ExtendedReinhardToneMapper {
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    fn map(&self, color: &Color) -> Color {
+        let l = luminance(color);
+        if l <= 0. {
+            return Color::new();
+        }
+
+        let white_2 = self.white_point * self.white_point;
+        let mapped = l * (1. + l / white_2) / (1. + l);
+        *color * (mapped / l)
+    }
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+pub struct AcesToneMapper {
+    exposure: f32,
+}
+
+impl AcesToneMapper {
+    pub fn new(exposure: f32) -> Self {
+        Self { exposure }
+    }
+}
+
+impl ToneMapper for AcesToneMapper {
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    fn map(&self, color: &Color) -> Color {
+        let mapped = color
+            .data
+            .map(|x| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14));
+        Color::from_values(mapped).saturate()
+    }
+}
+
+// John Hable's Uncharted 2 filmic curve.
+pub struct HableToneMapper {
+    exposure: f32,
+}
+
+impl HableToneMapper {
+    pub fn new(exposure: f32) -> Self {
+        Self { exposure }
+    }
+
+    fn curve(x: f32) -> f32 {
+        let a = 0.15;
+        let b = 0.50;
+        let c = 0.10;
+        let d = 0.20;
+        let e = 0.02;
+        let f = 0.30;
+        ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+    }
+}
+
+impl ToneMapper for HableToneMapper {
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    fn map(&self, color: &Color) -> Color {
+        let exposure_bias = 2.0;
+        let white_point = 11.2;
+        let white_scale = 1. / Self::curve(white_point);
+        let mapped = color
+            .data
+            .map(|x| Self::curve(x * exposure_bias) * white_scale);
+        Color::from_values(mapped)
+    }
+}
+
+#[cfg(test)]
+mod tone_mapping_tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_oetf() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 0.00001);
+        assert!((srgb_oetf(0.18) - 0.4614).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_exposure() {
+        let tone_mapper = ReinhardToneMapper::new(1.0);
+        assert_eq!(tone_mapper.display(&Color::splat(0.5)), [188; 3]);
+        assert_eq!(
+            LinearToneMapper::new(0.0).display(&Color::splat(4.0)),
+            [255; 3]
+        );
+    }
+
+    #[test]
+    fn test_extended_reinhard_white_point() {
+        let tone_mapper = ExtendedReinhardToneMapper::new(0.0, 4.0);
+        let white = tone_mapper.map(&Color::splat(4.0));
+        assert!((white.x() - 1.0).abs() < 0.00001);
+    }
+}
@@ -0,0 +1,297 @@
+use toy_tracer::intersection::Intersection;
+use toy_tracer::ray::Ray;
+use toy_tracer::types::*;
+use toy_tracer::vec::{dot, length, normalize, ZAccessor};
+use toy_tracer::*;
+
+#[test]
+fn test_intersect_through_public_api() {
+    let mut resources = Resources::default();
+    let white = resources.add_texture(SolidColorTexture::new(&Color::ones()));
+    let material = resources.add_material(DiffuseMaterial::new(white));
+    let sphere = resources.add_hittable(Sphere::new(1.0, &Position::new()));
+    let instances = vec![
+        Instance::new(sphere, 0, material, false),
+        Instance::new(sphere, 1, material, false).with_position(0., 0., -10.),
+    ];
+    let scene = TopLevelAccelerationStructure::new(resources.hittables(), &instances);
+    let camera = DefaultCamera::new(
+        &Position::from_values([0., 0., 5.]),
+        &Position::new(),
+        1.0,
+        45.,
+        0.,
+        5.,
+    );
+    let tracer = CPUTracer::new(RayGenerator { camera });
+
+    let ray = Ray::new(
+        &Position::from_values([0., 0., 5.]),
+        &Direction::from_values([0., 0., -1.]),
+    );
+    let (instance_id, intersection) = tracer
+        .intersect(&ray, &scene, &resources, 0.001, 1000.)
+        .unwrap();
+    assert_eq!(instance_id, 0);
+    assert!((intersection.t - 4.).abs() < 0.0001);
+}
+
+#[test]
+fn test_occluded_through_public_api() {
+    let mut resources = Resources::default();
+    let white = resources.add_texture(SolidColorTexture::new(&Color::ones()));
+    let material = resources.add_material(DiffuseMaterial::new(white));
+    let sphere = resources.add_hittable(Sphere::new(1.0, &Position::new()));
+    let triangle = resources.add_hittable(TriangleMesh::new(
+        vec![
+            Position::from_values([-1., -1., 0.]),
+            Position::from_values([1., -1., 0.]),
+            Position::from_values([0., 1., 0.]),
+        ],
+        Vec::new(),
+        Vec::new(),
+        vec![0, 1, 2],
+    ));
+    let instances = vec![
+        Instance::new(sphere, 0, material, false).with_position(0., 0., -10.),
+        Instance::new(triangle, 1, material, false)
+            .with_position(0., 0., -20.)
+            .with_uniform_scale(4.),
+    ];
+    let scene = TopLevelAccelerationStructure::new(resources.hittables(), &instances);
+    let camera = DefaultCamera::new(
+        &Position::from_values([0., 0., 5.]),
+        &Position::new(),
+        1.0,
+        45.,
+        0.,
+        5.,
+    );
+    let tracer = CPUTracer::new(RayGenerator { camera });
+
+    let ray = Ray::new(&Position::new(), &Direction::from_values([0., 0., -1.]));
+    assert!(!tracer.occluded(&ray, &scene, &resources, 0.001, 8.));
+    assert!(tracer.occluded(&ray, &scene, &resources, 0.001, 10.));
+
+    // Past the sphere only the triangle is in the way.
+    let ray = Ray::new(
+        &Position::from_values([1.5, 0., 0.]),
+        &Direction::from_values([0., 0., -1.]),
+    );
+    assert!(!tracer.occluded(&ray, &scene, &resources, 0.001, 19.));
+    assert!(tracer.occluded(&ray, &scene, &resources, 0.001, 21.));
+}
+
+#[test]
+fn test_intersect_non_uniformly_scaled_sphere() {
+    let mut resources = Resources::default();
+    let white = resources.add_texture(SolidColorTexture::new(&Color::ones()));
+    let material = resources.add_material(DiffuseMaterial::new(white));
+    let sphere = resources.add_hittable(Sphere::new(1.0, &Position::new()));
+    let instances = vec![Instance::new(sphere, 0, material, false)
+        .with_scale(1., 1., 4.)
+        .with_position(0., 2., 0.)];
+    let scene = TopLevelAccelerationStructure::new(resources.hittables(), &instances);
+    let camera = DefaultCamera::new(
+        &Position::from_values([0., 2., 10.]),
+        &Position::from_values([0., 2., 0.]),
+        1.0,
+        45.,
+        0.,
+        10.,
+    );
+    let tracer = CPUTracer::new(RayGenerator { camera });
+
+    let ray = Ray::new(
+        &Position::from_values([0., 2., 10.]),
+        &Direction::from_values([0., 0., -1.]),
+    );
+    let (_, intersection) = tracer
+        .intersect(&ray, &scene, &resources, 0.001, 1000.)
+        .unwrap();
+    assert!((intersection.t - 6.).abs() < 0.0001);
+    assert!((intersection.ray.at(intersection.t).z() - 4.).abs() < 0.0001);
+
+    // The normal follows the gradient of x^2 + (z / 4)^2 on the side.
+    let ray = Ray::new(
+        &Position::from_values([10., 2., 2.]),
+        &Direction::from_values([-1., 0., 0.]),
+    );
+    let (instance_id, intersection) = tracer
+        .intersect(&ray, &scene, &resources, 0.001, 1000.)
+        .unwrap();
+    let instance = &instances[instance_id as usize];
+    let normal = resources
+        .hittable(instance.geometry_index)
+        .normal(instance, &intersection);
+    let expected = normalize(&Direction::from_values([0.75f32.sqrt(), 0., 0.125]));
+    assert!(dot(&normal, &expected) > 0.9999);
+}
+
+#[test]
+fn test_intersect_millimeter_scaled_sphere() {
+    let mut resources = Resources::default();
+    let white = resources.add_texture(SolidColorTexture::new(&Color::ones()));
+    let material = resources.add_material(DiffuseMaterial::new(white));
+    let sphere = resources.add_hittable(Sphere::new(1.0, &Position::new()));
+    let instances = vec![Instance::new(sphere, 0, material, false).with_uniform_scale(0.001)];
+    let scene = TopLevelAccelerationStructure::new(resources.hittables(), &instances);
+    let camera = DefaultCamera::new(
+        &Position::from_values([0., 0., 1.]),
+        &Position::new(),
+        1.0,
+        45.,
+        0.,
+        1.,
+    );
+    let tracer = CPUTracer::new(RayGenerator { camera });
+
+    let ray = Ray::new(
+        &Position::from_values([0., 0., 1.]),
+        &Direction::from_values([0., 0., -1.]),
+    );
+    let (_, intersection) = tracer
+        .intersect(&ray, &scene, &resources, 0.0001, 1000.)
+        .unwrap();
+    assert!((intersection.t - 0.999).abs() < 0.0001);
+}
+
+struct ConstantHitShader(Color);
+
+impl ClosestHitShader for ConstantHitShader {
+    fn hit(&self, _: &ShaderContext, _: u32, _: &Intersection, payload: &mut Payload) {
+        payload.radiance = self.0;
+    }
+}
+
+struct ConstantMissShader(Color);
+
+impl MissShader for ConstantMissShader {
+    fn miss(&self, _: &ShaderContext, _: &Ray, payload: &mut Payload) {
+        payload.radiance = self.0;
+    }
+}
+
+// Lets rays pass through the upper half of the geometry.
+struct CutOutShader {}
+
+impl AnyHitShader for CutOutShader {
+    fn any_hit(&self, _: &Resources, _: u32, intersection: &Intersection) -> bool {
+        intersection.ray.at(intersection.t)[1] < 0.
+    }
+}
+
+#[test]
+fn test_shader_binding_table() {
+    let mut resources = Resources::default();
+    let white = resources.add_texture(SolidColorTexture::new(&Color::ones()));
+    let material = resources.add_material(DiffuseMaterial::new(white));
+    let sphere = resources.add_hittable(Sphere::new(1.0, &Position::new()));
+
+    let mut table = ShaderBindingTable::new();
+    let red = table.add_hit_group(ConstantHitShader(Color::from_values([1., 0., 0.])));
+    let green = table.add_hit_group_with_any_hit(
+        ConstantHitShader(Color::from_values([0., 1., 0.])),
+        CutOutShader {},
+    );
+    table.add_miss_shader(ConstantMissShader(Color::from_values([0., 0., 1.])));
+
+    let instances = vec![
+        Instance::new(sphere, 0, material, false)
+            .with_position(0., 0., -10.)
+            .with_hit_shader(red),
+        Instance::new(sphere, 1, material, false)
+            .with_position(0., 0., -5.)
+            .with_hit_shader(green),
+        // Not registered, so it falls back to the first hit group.
+        Instance::new(sphere, 2, material, false)
+            .with_position(5., 0., -10.)
+            .with_hit_shader(7),
+    ];
+    let scene = TopLevelAccelerationStructure::new(resources.hittables(), &instances);
+    let lights = Lights::new();
+    let camera = DefaultCamera::new(
+        &Position::from_values([0., 0., 5.]),
+        &Position::new(),
+        1.0,
+        45.,
+        0.,
+        5.,
+    );
+    let tracer = CPUTracer::new(RayGenerator { camera })
+        .with_shader_binding_table(table)
+        .unwrap();
+    let context = ShaderContext {
+        ray_tracer: &tracer,
+        scene: &scene,
+        lights: &lights,
+        resources: &resources,
+    };
+
+    let trace = |origin: [f32; 3]| {
+        let ray = Ray::new(
+            &Position::from_values(origin),
+            &Direction::from_values([0., 0., -1.]),
+        );
+        let mut payload = Payload::default();
+        tracer.trace_ray(&context, &ray, 0.001, 1000., 0, &mut payload);
+        payload.radiance
+    };
+
+    // The cut out front sphere is only hit on its lower half.
+    assert_eq!(trace([0., -0.5, 0.]), Color::from_values([0., 1., 0.]));
+    assert_eq!(trace([0., 0.5, 0.]), Color::from_values([1., 0., 0.]));
+    assert_eq!(trace([5., 0., 0.]), Color::from_values([1., 0., 0.]));
+    assert_eq!(trace([10., 0., 0.]), Color::from_values([0., 0., 1.]));
+    let mut payload = Payload::default();
+    let ray = Ray::new(&Position::new(), &Direction::from_values([0., 1., 0.]));
+    tracer.trace_ray(&context, &ray, 0.001, 1000., 3, &mut payload);
+    assert_eq!(payload.radiance, Color::from_values([0., 0., 1.]));
+    assert!(ShaderBindingTable::new().validate().is_err());
+    assert!(!tracer.occluded(
+        &Ray::new(
+            &Position::from_values([0., 0.5, 0.]),
+            &Direction::from_values([0., 0., -1.]),
+        ),
+        &scene,
+        &resources,
+        0.001,
+        8.,
+    ));
+}
+
+#[test]
+fn test_sample_surface() {
+    let sphere = Sphere::new(1.0, &Position::new());
+    let quad = TriangleMesh::new(
+        vec![
+            Position::from_values([0., 0., 0.]),
+            Position::from_values([1., 0., 0.]),
+            Position::from_values([1., 1., 0.]),
+            Position::from_values([0., 1., 0.]),
+        ],
+        Vec::new(),
+        Vec::new(),
+        vec![0, 1, 2, 0, 2, 3],
+    );
+    let instance = Instance::new(Default::default(), 0, Default::default(), false)
+        .with_position(0., 0., -5.)
+        .with_uniform_scale(2.);
+    let origin = Position::new();
+
+    // Averaging 1 / pdf over the samples estimates the area.
+    for (geometry, area) in [
+        (&sphere as &dyn Hittable, 16. * std::f32::consts::PI),
+        (&quad as &dyn Hittable, 4.),
+    ] {
+        let count = 1000;
+        let mut estimate = 0.;
+        for _ in 0..count {
+            let (intersection, pdf) = geometry.sample_surface(&instance, &origin).unwrap();
+            assert!((geometry.surface_pdf(&instance, &intersection) - pdf).abs() < 0.001 * pdf);
+            assert!((intersection.t - length(&intersection.ray.at(intersection.t))).abs() < 0.001);
+            estimate += 1. / pdf / count as f32;
+        }
+        assert!((estimate - area).abs() < 0.001 * area);
+    }
+}